@@ -0,0 +1,387 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned as _, Data, DeriveInput, Error, Expr, Fields,
+    Generics, ItemFn, Member, Path, ReturnType,
+};
+
+/// Derive `modelcheck::Arbitrary` for a struct or enum.
+///
+/// Fields are generated with their own `Arbitrary` implementation unless annotated:
+/// - `#[arbitrary(range = a..b)]` draws the field with `Gen::gen_range(g, a..b)`.
+/// - `#[arbitrary(with = path)]` calls `path(g)`, where `g: &mut modelcheck::Gen`.
+///
+/// Enum variants are chosen uniformly unless annotated with `#[arbitrary(weight = n)]`. A weight
+/// of 0 excludes the variant.
+#[proc_macro_derive(Arbitrary, attributes(arbitrary))]
+pub fn derive_arbitrary(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    arbitrary(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derive `modelcheck::Shrink` for a struct or enum.
+///
+/// Each field is shrunk in turn, keeping the variant and other fields unchanged. Fields annotated
+/// `#[arbitrary(range = ...)]` only shrink to values within the range, and fields annotated
+/// `#[arbitrary(with = ...)]` are not shrunk.
+#[proc_macro_derive(Shrink, attributes(arbitrary))]
+pub fn derive_shrink(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    shrink(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derive `modelcheck::ToRust` for a struct or enum, writing it as a struct or variant expression
+/// that refers to the type by name.
+#[proc_macro_derive(ToRust)]
+pub fn derive_to_rust(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    to_rust(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Turn a function returning a `modelcheck::ModelChecker` into a test that checks the model with
+/// `ModelChecker::check`, and panics with the run report if any run fails.
+///
+/// ```ignore
+/// #[modelcheck(runs = 1000, max_steps = 50)]
+/// fn stack() -> ModelChecker<Stack> {
+///     ModelChecker::default()
+/// }
+/// ```
+///
+/// Arguments set fields of `modelcheck::Config`: `runs`, `min_steps`, `max_steps`,
+/// `time_budget` (a `Duration`), `stop_on_first_failure` and `threads`. Settings given by
+/// `MODELCHECK_*` environment variables or `modelcheck.toml` take precedence over them, as applied
+/// by `Config::with_env`.
+#[proc_macro_attribute]
+pub fn modelcheck(args: TokenStream, input: TokenStream) -> TokenStream {
+    const FIELDS: [&str; 6] = [
+        "runs",
+        "min_steps",
+        "max_steps",
+        "time_budget",
+        "stop_on_first_failure",
+        "threads",
+    ];
+    let mut settings = Vec::new();
+    let parser = syn::meta::parser(|meta| {
+        let field = meta
+            .path
+            .get_ident()
+            .filter(|ident| FIELDS.iter().any(|f| ident == f));
+        let Some(field) = field.cloned() else {
+            let message = "expected `runs`, `min_steps`, `max_steps`, `time_budget`, \
+                           `stop_on_first_failure` or `threads`";
+            return Err(meta.error(message));
+        };
+        let value: Expr = meta.value()?.parse()?;
+        settings.push(if field == "time_budget" {
+            quote!(config.#field = ::std::option::Option::Some(#value);)
+        } else {
+            quote!(config.#field = #value;)
+        });
+        Ok(())
+    });
+    parse_macro_input!(args with parser);
+    let input = parse_macro_input!(input as ItemFn);
+    test(settings, input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn test(settings: Vec<TokenStream2>, input: ItemFn) -> syn::Result<TokenStream2> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = input;
+    if !sig.inputs.is_empty() || !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+        let message = "`modelcheck` functions take no arguments or generic parameters";
+        return Err(Error::new(sig.span(), message));
+    }
+    let ReturnType::Type(_, checker) = &sig.output else {
+        let message = "`modelcheck` functions must return a `ModelChecker`";
+        return Err(Error::new(sig.span(), message));
+    };
+    let name = &sig.ident;
+    Ok(quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #name() {
+            fn checker() -> #checker #block
+            #[allow(unused_mut)]
+            let mut config = ::modelcheck::Config::default();
+            #(#settings)*
+            let report = checker().check(&config.with_env());
+            if !report.is_ok() {
+                ::std::panic!("{}", report);
+            }
+        }
+    })
+}
+
+/// Options given by `#[arbitrary(...)]` attributes.
+#[derive(Default)]
+struct Options {
+    weight: Option<u64>,
+    range: Option<Expr>,
+    with: Option<Path>,
+}
+
+impl Options {
+    fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("arbitrary")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("weight") {
+                    let weight: syn::LitInt = meta.value()?.parse()?;
+                    options.weight = Some(weight.base10_parse()?);
+                } else if meta.path.is_ident("range") {
+                    options.range = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("with") {
+                    options.with = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `weight`, `range` or `with`"));
+                }
+                Ok(())
+            })?;
+        }
+        if options.range.is_some() && options.with.is_some() {
+            return Err(Error::new(
+                attrs[0].span(),
+                "`range` and `with` cannot be combined",
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// A field and its options.
+struct Field {
+    member: Member,
+    options: Options,
+}
+
+fn fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(index, field)| {
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(index.into()),
+            };
+            let options = Options::parse(&field.attrs)?;
+            if options.weight.is_some() {
+                let message = "`weight` only applies to enum variants";
+                return Err(Error::new(field.span(), message));
+            }
+            Ok(Field { member, options })
+        })
+        .collect()
+}
+
+/// Add `bound` to every type parameter.
+fn add_bounds(generics: &mut Generics, bound: syn::TypeParamBound) {
+    for param in generics.type_params_mut() {
+        param.bounds.push(bound.clone());
+    }
+}
+
+fn arbitrary(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    add_bounds(&mut input.generics, parse_quote!(::modelcheck::Arbitrary));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+
+    let body = match &input.data {
+        Data::Struct(data) => construct(quote!(Self), &fields(&data.fields)?),
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            let mut total: u64 = 0;
+            for variant in &data.variants {
+                let options = Options::parse(&variant.attrs)?;
+                if options.range.is_some() || options.with.is_some() {
+                    let message = "only `weight` applies to enum variants";
+                    return Err(Error::new(variant.span(), message));
+                }
+                let weight = options.weight.unwrap_or(1);
+                if weight == 0 {
+                    continue;
+                }
+                let ident = &variant.ident;
+                let value = construct(quote!(Self::#ident), &fields(&variant.fields)?);
+                let (start, end) = (total, total + weight - 1);
+                arms.push(quote!(#start..=#end => #value,));
+                total += weight;
+            }
+            if total == 0 {
+                let message = "`Arbitrary` requires a variant with nonzero weight";
+                return Err(Error::new(input.span(), message));
+            }
+            quote! {
+                match ::modelcheck::Gen::gen_range(g, 0..#total) {
+                    #(#arms)*
+                    _ => unreachable!(),
+                }
+            }
+        }
+        Data::Union(_) => {
+            let message = "`Arbitrary` cannot be derived for unions";
+            return Err(Error::new(input.span(), message));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::modelcheck::Arbitrary for #name #ty_generics #where_clause {
+            fn gen(g: &mut ::modelcheck::Gen) -> Self {
+                #body
+            }
+        }
+    })
+}
+
+/// Expression constructing `path` with generated fields.
+fn construct(path: TokenStream2, fields: &[Field]) -> TokenStream2 {
+    let values = fields.iter().map(|field| {
+        let member = &field.member;
+        let value = match &field.options {
+            Options {
+                range: Some(range), ..
+            } => quote!(::modelcheck::Gen::gen_range(g, #range)),
+            Options {
+                with: Some(with), ..
+            } => quote!(#with(g)),
+            _ => quote!(::modelcheck::Arbitrary::gen(g)),
+        };
+        quote!(#member: #value)
+    });
+    quote!(#path { #(#values),* })
+}
+
+fn shrink(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    add_bounds(&mut input.generics, parse_quote!(::modelcheck::Shrink));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+
+    let arms = match &input.data {
+        Data::Struct(data) => vec![shrink_arm(quote!(Self), &fields(&data.fields)?)],
+        Data::Enum(data) => (data.variants.iter())
+            .map(|variant| {
+                let ident = &variant.ident;
+                Ok(shrink_arm(quote!(Self::#ident), &fields(&variant.fields)?))
+            })
+            .collect::<syn::Result<_>>()?,
+        Data::Union(_) => {
+            let message = "`Shrink` cannot be derived for unions";
+            return Err(Error::new(input.span(), message));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::modelcheck::Shrink for #name #ty_generics #where_clause {
+            #[allow(irrefutable_let_patterns, unreachable_code)]
+            fn shrink(&self) -> ::std::boxed::Box<dyn ::std::iter::Iterator<Item = Self>> {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Match arm shrinking each field of `path` in turn.
+fn shrink_arm(path: TokenStream2, fields: &[Field]) -> TokenStream2 {
+    let chains = fields
+        .iter()
+        .filter(|field| field.options.with.is_none())
+        .map(|field| {
+            let member = &field.member;
+            let filter = (field.options.range.as_ref())
+                .map(|range| quote!(.filter(|x| (#range).contains(x))));
+            quote! {
+                let candidates = candidates.chain({
+                    let this = ::std::clone::Clone::clone(self);
+                    let #path { #member: field, .. } = self else { unreachable!() };
+                    ::modelcheck::Shrink::shrink(field)
+                        #filter
+                        .map(move |x| {
+                            let mut shrunk = ::std::clone::Clone::clone(&this);
+                            if let #path { #member: field, .. } = &mut shrunk {
+                                *field = x;
+                            }
+                            shrunk
+                        })
+                });
+            }
+        });
+    quote! {
+        #path { .. } => {
+            let candidates = ::std::iter::empty::<Self>();
+            #(#chains)*
+            ::std::boxed::Box::new(candidates)
+        }
+    }
+}
+
+fn to_rust(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    add_bounds(&mut input.generics, parse_quote!(::modelcheck::ToRust));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+
+    let arms = match &input.data {
+        Data::Struct(data) => vec![to_rust_arm(quote!(Self), name.to_string(), &data.fields)],
+        Data::Enum(data) => (data.variants.iter())
+            .map(|variant| {
+                let ident = &variant.ident;
+                let text = format!("{name}::{ident}");
+                to_rust_arm(quote!(Self::#ident), text, &variant.fields)
+            })
+            .collect(),
+        Data::Union(_) => {
+            let message = "`ToRust` cannot be derived for unions";
+            return Err(Error::new(input.span(), message));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::modelcheck::ToRust for #name #ty_generics #where_clause {
+            fn to_rust(&self) -> ::std::string::String {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Match arm writing `path`, spelled `text` in the output, with its fields.
+fn to_rust_arm(path: TokenStream2, text: String, fields: &Fields) -> TokenStream2 {
+    let members = fields.members();
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|index| quote::format_ident!("field{index}"))
+        .collect();
+    let format = match fields {
+        Fields::Named(fields) => {
+            let names = fields.named.iter().map(|field| {
+                let ident = field.ident.as_ref().unwrap();
+                format!("{ident}: {{}}")
+            });
+            format!("{text} {{{{ {} }}}}", names.collect::<Vec<_>>().join(", "))
+        }
+        Fields::Unnamed(_) => format!("{text}({})", vec!["{}"; fields.len()].join(", ")),
+        Fields::Unit => text,
+    };
+    quote! {
+        #path { #(#members: #bindings,)* .. } => ::std::format!(
+            #format,
+            #(::modelcheck::ToRust::to_rust(#bindings)),*
+        ),
+    }
+}
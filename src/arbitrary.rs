@@ -0,0 +1,202 @@
+use crate::{Arbitrary, Gen};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::Hash,
+};
+
+/// With probability 1/4, pick one of `edges` instead of a uniform value.
+fn edge<T: Copy>(g: &mut Gen, edges: &[T]) -> Option<T> {
+    if g.gen_ratio(1, 4) {
+        g.choose(edges).copied()
+    } else {
+        None
+    }
+}
+
+impl Arbitrary for () {
+    fn gen(_: &mut Gen) -> Self {}
+}
+
+impl Arbitrary for bool {
+    fn gen(g: &mut Gen) -> Self {
+        g.gen_ratio(1, 2)
+    }
+}
+
+// `usize` and `isize` are sampled as 64-bit values and truncated, so that a seed generates the
+// same values on targets of any pointer width.
+macro_rules! arbitrary_unsigned {
+    ($($t:ty: $via:ty),*) => {$(
+        impl Arbitrary for $t {
+            fn gen(g: &mut Gen) -> Self {
+                let edges = [0, 1, 2, <$t>::MAX - 1, <$t>::MAX];
+                edge(g, &edges).unwrap_or_else(|| g.gen_range(<$via>::MIN..=<$via>::MAX) as $t)
+            }
+        }
+    )*};
+}
+arbitrary_unsigned!(u8: u8, u16: u16, u32: u32, u64: u64, u128: u128, usize: u64);
+
+macro_rules! arbitrary_signed {
+    ($($t:ty: $via:ty),*) => {$(
+        impl Arbitrary for $t {
+            fn gen(g: &mut Gen) -> Self {
+                let edges = [0, 1, -1, <$t>::MIN, <$t>::MIN + 1, <$t>::MAX - 1, <$t>::MAX];
+                edge(g, &edges).unwrap_or_else(|| g.gen_range(<$via>::MIN..=<$via>::MAX) as $t)
+            }
+        }
+    )*};
+}
+arbitrary_signed!(i8: i8, i16: i16, i32: i32, i64: i64, i128: i128, isize: i64);
+
+macro_rules! arbitrary_float {
+    ($($t:ident),*) => {$(
+        impl Arbitrary for $t {
+            fn gen(g: &mut Gen) -> Self {
+                use std::$t::consts::PI;
+                let edges = [
+                    0.0,
+                    -0.0,
+                    1.0,
+                    -1.0,
+                    PI,
+                    $t::EPSILON,
+                    $t::MIN_POSITIVE,
+                    $t::MIN,
+                    $t::MAX,
+                    $t::INFINITY,
+                    $t::NEG_INFINITY,
+                    $t::NAN,
+                ];
+                edge(g, &edges).unwrap_or_else(|| {
+                    // spread values over several orders of magnitude
+                    let exponent = g.gen_range(-8..=8);
+                    g.gen_range::<$t, _>(-1.0..1.0) * (10.0 as $t).powi(exponent)
+                })
+            }
+        }
+    )*};
+}
+arbitrary_float!(f32, f64);
+
+impl Arbitrary for char {
+    fn gen(g: &mut Gen) -> Self {
+        let edges = [
+            '\0',
+            '\t',
+            '\n',
+            ' ',
+            '\u{7f}',
+            '\u{80}',
+            'é',
+            'ß',
+            'Ω',
+            '日',
+            '\u{200b}', // zero width space
+            '\u{feff}', // byte order mark
+            '\u{fffd}', // replacement character
+            '🦀',
+            char::MAX,
+        ];
+        if let Some(c) = edge(g, &edges) {
+            return c;
+        }
+        if g.gen_bool(0.5) {
+            g.gen_range(' '..='~')
+        } else {
+            g.gen_range('\0'..=char::MAX)
+        }
+    }
+}
+
+impl Arbitrary for String {
+    fn gen(g: &mut Gen) -> Self {
+        let len = g.gen_len();
+        (0..len).map(|_| char::gen(g)).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Box<T> {
+    fn gen(g: &mut Gen) -> Self {
+        Box::new(T::gen(g))
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Option<T> {
+    fn gen(g: &mut Gen) -> Self {
+        if g.gen_ratio(1, 4) {
+            None
+        } else {
+            Some(T::gen(g))
+        }
+    }
+}
+
+impl<T: Arbitrary, E: Arbitrary> Arbitrary for Result<T, E> {
+    fn gen(g: &mut Gen) -> Self {
+        if g.gen_ratio(1, 4) {
+            Err(E::gen(g))
+        } else {
+            Ok(T::gen(g))
+        }
+    }
+}
+
+impl<T: Arbitrary, const N: usize> Arbitrary for [T; N] {
+    fn gen(g: &mut Gen) -> Self {
+        std::array::from_fn(|_| T::gen(g))
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for Vec<T> {
+    fn gen(g: &mut Gen) -> Self {
+        let len = g.gen_len();
+        (0..len).map(|_| T::gen(g)).collect()
+    }
+}
+
+impl<T: Arbitrary> Arbitrary for VecDeque<T> {
+    fn gen(g: &mut Gen) -> Self {
+        Vec::gen(g).into()
+    }
+}
+
+impl<T: Arbitrary + Ord> Arbitrary for BTreeSet<T> {
+    fn gen(g: &mut Gen) -> Self {
+        Vec::gen(g).into_iter().collect()
+    }
+}
+
+impl<K: Arbitrary + Ord, V: Arbitrary> Arbitrary for BTreeMap<K, V> {
+    fn gen(g: &mut Gen) -> Self {
+        Vec::<(K, V)>::gen(g).into_iter().collect()
+    }
+}
+
+impl<T: Arbitrary + Eq + Hash> Arbitrary for HashSet<T> {
+    fn gen(g: &mut Gen) -> Self {
+        Vec::gen(g).into_iter().collect()
+    }
+}
+
+impl<K: Arbitrary + Eq + Hash, V: Arbitrary> Arbitrary for HashMap<K, V> {
+    fn gen(g: &mut Gen) -> Self {
+        Vec::<(K, V)>::gen(g).into_iter().collect()
+    }
+}
+
+macro_rules! arbitrary_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Arbitrary),+> Arbitrary for ($($name,)+) {
+            fn gen(g: &mut Gen) -> Self {
+                ($($name::gen(g),)+)
+            }
+        }
+    };
+}
+arbitrary_tuple!(A);
+arbitrary_tuple!(A, B);
+arbitrary_tuple!(A, B, C);
+arbitrary_tuple!(A, B, C, D);
+arbitrary_tuple!(A, B, C, D, E);
+arbitrary_tuple!(A, B, C, D, E, F);
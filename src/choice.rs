@@ -0,0 +1,235 @@
+use crate::{
+    failure,
+    isolation::{Executor, Termination},
+    minimize::ddmin,
+    DefaultRng, Failure, FailureIdentity, Generated, ModelChecker, ModelState, Outcome,
+    Shrink as _,
+};
+use rand::{Error, RngCore};
+use std::ops::Range;
+
+/// How a checker generates traces and shrinks failing ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Generation {
+    /// Generate values directly from the RNG and simplify them with `Shrink`.
+    #[default]
+    Random,
+    /// Record every value `Arbitrary::gen` draws from its `Gen`, and shrink that choice sequence
+    /// instead of the generated values. Shrunk states and steps are always produced by
+    /// `Arbitrary::gen`, so they satisfy the generators' invariants, and `Shrink` is not used.
+    ///
+    /// A failure found in this mode has the same unshrunk trace as in `Random` mode.
+    Choices,
+}
+
+/// Random source that records the values it produces, or replays a recorded sequence. Once a
+/// replayed sequence is exhausted, every further draw is 0.
+pub(crate) struct ChoiceSource {
+    choices: Vec<u64>,
+    position: usize,
+    rng: Option<DefaultRng>,
+}
+
+impl ChoiceSource {
+    pub fn record(rng: DefaultRng) -> Self {
+        Self {
+            choices: Vec::new(),
+            position: 0,
+            rng: Some(rng),
+        }
+    }
+
+    pub fn replay(choices: Vec<u64>) -> Self {
+        Self {
+            choices,
+            position: 0,
+            rng: None,
+        }
+    }
+
+    fn draw(&mut self, fresh: impl FnOnce(&mut DefaultRng) -> u64) -> u64 {
+        if self.position == self.choices.len() {
+            let choice = self.rng.as_mut().map_or(0, fresh);
+            self.choices.push(choice);
+        }
+        self.position += 1;
+        self.choices[self.position - 1]
+    }
+}
+
+impl RngCore for ChoiceSource {
+    fn next_u32(&mut self) -> u32 {
+        let choice = self.draw(|rng| rng.next_u32().into());
+        choice.min(u32::MAX.into()) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draw(RngCore::next_u64)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// A trace generated from a choice sequence.
+pub(crate) struct ChoiceTrace<M: ModelState> {
+    pub state: M,
+    pub steps: Vec<M::Step>,
+    pub choices: Vec<u64>,
+    /// Number of choices used to generate the initial state.
+    state_len: usize,
+    /// Range of `choices` used to generate each step.
+    spans: Vec<Range<usize>>,
+}
+
+impl<M: ModelState> ChoiceTrace<M> {
+    /// Generate and execute a trace, as `ModelChecker::generate`.
+    pub fn generate(
+        mut source: ChoiceSource,
+        max_steps: usize,
+        size: usize,
+        terminated: Option<(u64, Termination)>,
+    ) -> Generated<(Self, Outcome<M::Error>), M::Error> {
+        let mut marks = Vec::new();
+        let (state, steps, result) =
+            ModelChecker::<M>::generate(&mut source, max_steps, size, terminated, |source| {
+                marks.push(source.position)
+            })?;
+        let mut choices = source.choices;
+        choices.truncate(source.position);
+        let trace = Self {
+            state,
+            steps,
+            choices,
+            state_len: marks[0],
+            spans: marks.windows(2).map(|w| w[0]..w[1]).collect(),
+        };
+        Ok((trace, result))
+    }
+
+    /// Keep only the first `steps` steps, and the choices used to generate them.
+    pub fn truncate(&mut self, steps: usize) {
+        self.steps.truncate(steps);
+        self.spans.truncate(steps);
+        let len = self.spans.last().map_or(self.state_len, |span| span.end);
+        self.choices.truncate(len);
+    }
+}
+
+/// Shrinks a failing trace by shrinking the choice sequence it was generated from.
+pub(crate) struct ChoiceMinimizer<M: ModelState> {
+    pub trace: ChoiceTrace<M>,
+    pub failure: Failure<M::Error>,
+    pub original: Failure<M::Error>,
+    identity: FailureIdentity<M::Error>,
+    max_steps: usize,
+    size: usize,
+    executor: Executor,
+    pub replays: usize,
+}
+
+impl<M: ModelState> ChoiceMinimizer<M> {
+    pub fn new(
+        trace: ChoiceTrace<M>,
+        failure: Failure<M::Error>,
+        identity: FailureIdentity<M::Error>,
+        max_steps: usize,
+        size: usize,
+        executor: Executor,
+    ) -> Self {
+        Self {
+            trace,
+            original: failure.clone(),
+            failure,
+            identity,
+            max_steps,
+            size,
+            executor,
+            replays: 0,
+        }
+    }
+
+    /// Alternate step removal and choice shrinking until neither makes progress.
+    pub fn minimize(&mut self) {
+        loop {
+            let removed = self.remove_steps();
+            let shrunk = self.shrink_choices();
+            if !removed && !shrunk {
+                break;
+            }
+        }
+    }
+
+    /// Regenerate a trace from `choices` and execute it. If it fails in the same way as the
+    /// original trace, and its choice sequence is simpler than the current one, it replaces the
+    /// current trace and the number of steps executed is returned.
+    fn test(&mut self, choices: Vec<u64>) -> Option<usize> {
+        self.replays += 1;
+        // a candidate whose generator panics, or crashes or hangs the child process, does not
+        // fail in the same way
+        let run = |terminated| {
+            let source = ChoiceSource::replay(choices.clone());
+            failure::catch(|| {
+                ChoiceTrace::<M>::generate(source, self.max_steps, self.size, terminated)
+            })
+            .and_then(|trace| trace)
+            .ok()
+        };
+        let failed = |trace: &Option<_>| matches!(trace, Some((_, Err(_))));
+        let (mut trace, result) = self.executor.execute(run, failed)??;
+        let (failure, executed) = result
+            .err()
+            .filter(|(failure, _)| self.identity.matches::<M>(&self.original, failure))?;
+        trace.truncate(executed);
+        // order choice sequences by length, then lexicographically, so shrinking terminates
+        let simpler =
+            (trace.choices.len(), &trace.choices) < (self.trace.choices.len(), &self.trace.choices);
+        if !simpler {
+            return None;
+        }
+        self.trace = trace;
+        self.failure = failure;
+        Some(executed)
+    }
+
+    /// Remove the choices of whole steps with `ddmin`. Returns true if any step was removed.
+    fn remove_steps(&mut self) -> bool {
+        let prefix = self.trace.choices[..self.trace.state_len].to_vec();
+        let mut steps: Vec<Vec<u64>> = (self.trace.spans.iter())
+            .map(|span| self.trace.choices[span.clone()].to_vec())
+            .collect();
+        ddmin(&mut steps, |candidate, _| {
+            let choices = prefix.iter().chain(candidate.iter().flatten()).copied();
+            self.test(choices.collect())
+        })
+    }
+
+    /// Replace each choice with the first of its `Shrink` candidates that still fails, until
+    /// none do. Returns true if any choice was replaced.
+    fn shrink_choices(&mut self) -> bool {
+        let mut shrunk = false;
+        let mut index = 0;
+        while let Some(&choice) = self.trace.choices.get(index) {
+            let replaced = choice.shrink().any(|candidate| {
+                let mut choices = self.trace.choices.clone();
+                choices[index] = candidate;
+                self.test(choices).is_some()
+            });
+            if replaced {
+                shrunk = true;
+            } else {
+                index += 1;
+            }
+        }
+        shrunk
+    }
+}
@@ -0,0 +1,131 @@
+/// Largest table of common subsequence lengths `diff` allocates, in entries.
+const MAX_TABLE: usize = 1 << 20;
+
+/// Diff two values rendered with `{:#?}`, which puts each field and element on its own line.
+///
+/// Removed and added lines are prefixed with `- ` and `+ `. Each change is preceded by the
+/// unchanged lines opening the structures that contain it, so the path to the change is visible,
+/// and other unchanged lines are omitted. Returns an empty string if the renderings are equal,
+/// and `None` if the changed lines are too many to compare.
+pub(crate) fn diff(old: &str, new: &str) -> Option<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // only the lines between the common prefix and suffix are compared
+    let start = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let end = old[start..]
+        .iter()
+        .rev()
+        .zip(new[start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (changed_old, changed_new) = (&old[start..old.len() - end], &new[start..new.len() - end]);
+    if (changed_old.len() + 1).saturating_mul(changed_new.len() + 1) > MAX_TABLE {
+        return None;
+    }
+
+    // lengths of the longest common subsequences of changed_old[i..] and changed_new[j..]
+    let (old_len, new_len) = (changed_old.len(), changed_new.len());
+    let mut lcs = vec![vec![0usize; new_len + 1]; old_len + 1];
+    for i in (0..old_len).rev() {
+        for j in (0..new_len).rev() {
+            lcs[i][j] = if changed_old[i] == changed_new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<(&str, &str)> = old[..start].iter().map(|&l| ("  ", l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_len || j < new_len {
+        lines.push(
+            if i < old_len && j < new_len && changed_old[i] == changed_new[j] {
+                i += 1;
+                j += 1;
+                ("  ", changed_old[i - 1])
+            } else if i < old_len && (j == new_len || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                i += 1;
+                ("- ", changed_old[i - 1])
+            } else {
+                j += 1;
+                ("+ ", changed_new[j - 1])
+            },
+        );
+    }
+    lines.extend(old[old.len() - end..].iter().map(|&l| ("  ", l)));
+
+    let mut output = Vec::new();
+    // unchanged lines opening the structures around the current position, and whether they
+    // have been written
+    let mut open: Vec<(&str, bool)> = Vec::new();
+    for (prefix, line) in lines {
+        let depth = indent(line);
+        while open.last().is_some_and(|&(l, _)| indent(l) >= depth) {
+            open.pop();
+        }
+        if prefix == "  " {
+            if line.ends_with(['{', '[', '(']) {
+                open.push((line, false));
+            }
+            continue;
+        }
+        for (l, written) in open.iter_mut().filter(|(_, written)| !written) {
+            output.push(format!("  {l}"));
+            *written = true;
+        }
+        output.push(format!("{prefix}{line}"));
+    }
+    Some(output.join("\n"))
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_debug() {
+        #[derive(Debug)]
+        #[allow(dead_code)]
+        struct Node {
+            id: u32,
+            children: Vec<u32>,
+            name: &'static str,
+        }
+        let old = Node {
+            id: 1,
+            children: vec![2, 3],
+            name: "a",
+        };
+        let new = Node {
+            children: vec![2, 4],
+            ..old
+        };
+        let changes = diff(&format!("{old:#?}"), &format!("{new:#?}")).unwrap();
+        let expected = [
+            "  Node {",
+            "      children: [",
+            "-         3,",
+            "+         4,",
+        ];
+        assert_eq!(changes, expected.join("\n"));
+        assert_eq!(
+            diff(&format!("{new:#?}"), &format!("{new:#?}")).unwrap(),
+            ""
+        );
+
+        // long changes are not compared, but unchanged lines around them are not counted
+        let long: Vec<u32> = (0..2000).collect();
+        let reversed: Vec<u32> = long.iter().rev().copied().collect();
+        assert_eq!(diff(&format!("{long:#?}"), &format!("{reversed:#?}")), None);
+        let mut changed = long.clone();
+        changed[1000] = 0;
+        let changes = diff(&format!("{long:#?}"), &format!("{changed:#?}")).unwrap();
+        assert_eq!(changes, "  [\n-     1000,\n+     0,");
+    }
+}
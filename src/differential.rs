@@ -0,0 +1,113 @@
+use crate::{failure, Arbitrary, Gen, ModelState, Shrink};
+use std::fmt::Debug;
+
+/// A reference model: the specification a `SystemUnderTest` is checked against.
+pub trait Reference: Arbitrary + Shrink + Clone + Debug {
+    type Step: Arbitrary + Shrink + Clone + Debug;
+    type Output: PartialEq + Debug;
+
+    fn apply(&mut self, step: &Self::Step) -> Self::Output;
+
+    /// Whether the system's output for `step` is acceptable, given the reference model's output.
+    fn postcondition(
+        &self,
+        _step: &Self::Step,
+        expected: &Self::Output,
+        actual: &Self::Output,
+    ) -> bool {
+        expected == actual
+    }
+
+    /// As `ModelState::gen_step`.
+    fn gen_step(&self, g: &mut Gen) -> Self::Step {
+        Self::Step::gen(g)
+    }
+
+    /// As `ModelState::precondition`.
+    fn precondition(&self, _step: &Self::Step) -> bool {
+        true
+    }
+}
+
+/// The real system, checked against a `Reference` model.
+pub trait SystemUnderTest: Clone + Debug + 'static {
+    type Reference: Reference;
+
+    /// Create the system in the state described by the reference model.
+    fn new(reference: &Self::Reference) -> Self;
+
+    fn apply(&mut self, step: &Step<Self>) -> Output<Self>;
+}
+
+type Step<S> = <<S as SystemUnderTest>::Reference as Reference>::Step;
+type Output<S> = <<S as SystemUnderTest>::Reference as Reference>::Output;
+
+/// The outputs of a reference model and a system under test for the same step, which did not
+/// satisfy `Reference::postcondition`. The step and outputs are rendered with `Debug`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mismatch {
+    pub step: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// A `ModelState` applying each step to both a reference model and a system under test. A step
+/// fails by returning a `Mismatch` when the outputs do not satisfy `Reference::postcondition`.
+#[derive(Clone, Debug)]
+pub struct Differential<S: SystemUnderTest> {
+    pub reference: S::Reference,
+    pub system: S,
+}
+
+impl<S: SystemUnderTest> Differential<S> {
+    pub fn new(reference: S::Reference) -> Self {
+        Self {
+            system: S::new(&reference),
+            reference,
+        }
+    }
+}
+
+impl<S: SystemUnderTest> Arbitrary for Differential<S> {
+    fn gen(g: &mut Gen) -> Self {
+        Self::new(S::Reference::gen(g))
+    }
+}
+
+impl<S: SystemUnderTest> Shrink for Differential<S> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new(self.reference.shrink().map(Self::new))
+    }
+}
+
+impl<S: SystemUnderTest> ModelState for Differential<S> {
+    type Step = Step<S>;
+    type Error = Mismatch;
+
+    fn step(&mut self, step: Self::Step) -> Result<(), Mismatch> {
+        let expected = self.reference.apply(&step);
+        let actual = self.system.apply(&step);
+        if !self.reference.postcondition(&step, &expected, &actual) {
+            return Err(Mismatch {
+                step: format!("{step:?}"),
+                expected: format!("{expected:?}"),
+                actual: format!("{actual:?}"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Mismatches are the same bug when their steps are the same variant.
+    fn same_error(a: &Mismatch, b: &Mismatch) -> bool {
+        failure::same_variant(&a.step, &b.step)
+    }
+
+    fn gen_step(&self, g: &mut Gen) -> Self::Step {
+        self.reference.gen_step(g)
+    }
+
+    fn precondition(&self, step: &Self::Step) -> bool {
+        self.reference.precondition(step)
+    }
+}
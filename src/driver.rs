@@ -0,0 +1,281 @@
+use crate::{derive_seed, settings::Settings, FailedState, FailureStore, ModelChecker, ModelState};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Settings for `ModelChecker::check`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Maximum number of runs.
+    pub runs: u64,
+    /// The `max_steps` of the first run. Later runs grow linearly towards `max_steps`, so that
+    /// short traces are explored first.
+    pub min_steps: usize,
+    /// The `max_steps` of the last run.
+    pub max_steps: usize,
+    /// Wall-clock time after which no new run is started. A run in progress, including shrinking,
+    /// is not interrupted.
+    pub time_budget: Option<Duration>,
+    /// Stop after the first failing run. Otherwise every run is executed and each failure is
+    /// shrunk and reported.
+    pub stop_on_first_failure: bool,
+    /// Number of threads executing new runs. With more than one, runs are explored in parallel
+    /// and failing runs are then shrunk one at a time, producing the same report as a single
+    /// thread would, as long as the model is deterministic.
+    pub threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            runs: 100,
+            min_steps: 1,
+            max_steps: 100,
+            time_budget: None,
+            stop_on_first_failure: true,
+            threads: 1,
+        }
+    }
+}
+
+impl Config {
+    /// The default configuration, with settings given by `MODELCHECK_*` environment variables or
+    /// `modelcheck.toml` applied as by `with_env`.
+    pub fn from_env() -> Self {
+        Self::default().with_env()
+    }
+
+    /// Replace fields with the settings given by `MODELCHECK_RUNS`, `MODELCHECK_MIN_STEPS`,
+    /// `MODELCHECK_MAX_STEPS`, `MODELCHECK_TIME_BUDGET` (in seconds),
+    /// `MODELCHECK_STOP_ON_FIRST_FAILURE` and `MODELCHECK_THREADS`, or by the same keys in lower
+    /// case in `modelcheck.toml`. The file is read from the current directory, or from the path in
+    /// `MODELCHECK_CONFIG`, and environment variables take precedence over it.
+    ///
+    /// Fields set before this call defer to the environment, and fields set afterwards, e.g. with
+    /// `Config { runs: 10, ..Config::from_env() }`, override it.
+    ///
+    /// # Panics
+    ///
+    /// If the configuration file cannot be read or parsed, or if a value is invalid.
+    pub fn with_env(self) -> Self {
+        let settings = Settings::load();
+        Self {
+            runs: settings.runs.unwrap_or(self.runs),
+            min_steps: settings.min_steps.unwrap_or(self.min_steps),
+            max_steps: settings.max_steps.unwrap_or(self.max_steps),
+            time_budget: settings.time_budget.or(self.time_budget),
+            stop_on_first_failure: (settings.stop_on_first_failure)
+                .unwrap_or(self.stop_on_first_failure),
+            threads: settings.threads.unwrap_or(self.threads),
+        }
+    }
+
+    /// The `max_steps` of the `run`th run, counting from 0.
+    pub(crate) fn steps_for_run(&self, run: u64) -> usize {
+        let (min, max) = (self.min_steps, self.max_steps.max(self.min_steps));
+        if self.runs <= 1 {
+            return max;
+        }
+        let extra = (max - min) as u128 * run.min(self.runs - 1) as u128 / (self.runs - 1) as u128;
+        min + extra as usize
+    }
+}
+
+/// Summary of the runs executed by `ModelChecker::check`.
+#[derive(Debug)]
+pub struct RunReport<M: ModelState> {
+    /// Master seed of the checker.
+    pub seed: u64,
+    /// Number of runs replayed from the checker's `FailureStore`.
+    pub replayed: u64,
+    /// Failures of the replayed runs, in the order they are stored.
+    pub regressions: Vec<FailedState<M>>,
+    /// Number of new runs executed.
+    pub runs: u64,
+    /// Number of runs that did not fail.
+    pub passed: u64,
+    /// Wall-clock time of all runs, including shrinking.
+    pub duration: Duration,
+    /// Wall-clock time spent shrinking failures.
+    pub shrink_duration: Duration,
+    /// Whether runs were skipped because the time budget was exhausted.
+    pub timed_out: bool,
+    /// Shrunk failures of new runs, in the order they were found.
+    pub failures: Vec<FailedState<M>>,
+}
+
+impl<M: ModelState> RunReport<M> {
+    /// Whether every replayed and new run passed.
+    pub fn is_ok(&self) -> bool {
+        self.regressions.is_empty() && self.failures.is_empty()
+    }
+
+    /// The first failure found, if any, starting with regressions.
+    pub fn failure(&self) -> Option<&FailedState<M>> {
+        self.regressions.first().or(self.failures.first())
+    }
+}
+
+impl<M: ModelState> fmt::Display for RunReport<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} runs, {} passed, {} failed in {:.2?}",
+            self.runs,
+            self.passed,
+            self.failures.len(),
+            self.duration
+        )?;
+        if self.timed_out {
+            write!(f, " (time budget exhausted)")?;
+        }
+        if self.replayed > 0 {
+            let regressed = self.regressions.len();
+            write!(f, ", {regressed} of {} stored runs failed", self.replayed)?;
+        }
+        for failed in &self.regressions {
+            write!(f, "\n\nstored run {failed}")?;
+        }
+        for failed in &self.failures {
+            write!(f, "\n\nrun {} {failed}", failed.run)?;
+        }
+        if !self.failures.is_empty() {
+            let seed = self.seed;
+            write!(
+                f,
+                "\n\nrerun a checker created with `ModelChecker::default` "
+            )?;
+            write!(f, "with MODELCHECK_SEED={seed:#x} to repeat these runs")?;
+        }
+        Ok(())
+    }
+}
+
+impl<M: ModelState> ModelChecker<M> {
+    /// Replay the runs stored in the checker's `FailureStore`, and then execute new runs as
+    /// configured by `config`, continuing from the checker's previous runs. Stored runs are
+    /// always replayed, regardless of the time budget, on the calling thread.
+    ///
+    /// # Panics
+    ///
+    /// If the failure store cannot be read.
+    pub fn check(&mut self, config: &Config) -> RunReport<M> {
+        let start = Instant::now();
+        let mut report = RunReport {
+            seed: self.seed(),
+            replayed: 0,
+            regressions: Vec::new(),
+            runs: 0,
+            passed: 0,
+            duration: Duration::ZERO,
+            shrink_duration: Duration::ZERO,
+            timed_out: false,
+            failures: Vec::new(),
+        };
+        let stored = (self.store.as_ref()).map_or(Ok(Vec::new()), FailureStore::load::<M>);
+        for case in stored.unwrap_or_else(|e| panic!("failed to load stored failures: {e}")) {
+            report.replayed += 1;
+            if let Err(failed) = self.run_seed(case.seed, case.max_steps) {
+                report.shrink_duration += failed.shrink_duration;
+                report.regressions.push(failed);
+                if config.stop_on_first_failure {
+                    report.duration = start.elapsed();
+                    return report;
+                }
+            }
+        }
+        if config.threads > 1 {
+            self.check_parallel(config, start, &mut report);
+            report.duration = start.elapsed();
+            return report;
+        }
+        for run in 0..config.runs {
+            if config
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget)
+            {
+                report.timed_out = true;
+                break;
+            }
+            report.runs += 1;
+            match self.run(config.steps_for_run(run)) {
+                Ok(()) => report.passed += 1,
+                Err(failed) => {
+                    report.shrink_duration += failed.shrink_duration;
+                    report.failures.push(failed);
+                    if config.stop_on_first_failure {
+                        break;
+                    }
+                }
+            }
+        }
+        report.duration = start.elapsed();
+        report
+    }
+
+    /// Explore new runs on `config.threads` threads, which claim runs in order and only tell
+    /// whether they fail. Each run's RNG is seeded from the master seed and the run's number, so
+    /// the outcome does not depend on the thread executing it. Failing runs are then executed
+    /// again and shrunk on the calling thread.
+    ///
+    /// When stopping on the first failure, a failing run stops the threads from claiming later
+    /// runs, and earlier runs in progress are completed, so the failure reported is the first in
+    /// order, as it would be on a single thread.
+    fn check_parallel(&mut self, config: &Config, start: Instant, report: &mut RunReport<M>) {
+        let first = self.runs;
+        let next = AtomicU64::new(0);
+        // runs from `end` on are not executed
+        let end = AtomicU64::new(config.runs);
+        let timed_out = AtomicBool::new(false);
+        let failing = Mutex::new(Vec::new());
+        let checker = &*self;
+        thread::scope(|scope| {
+            for _ in 0..config.threads {
+                scope.spawn(|| loop {
+                    if config
+                        .time_budget
+                        .is_some_and(|budget| start.elapsed() >= budget)
+                    {
+                        if next.load(Ordering::Relaxed) < end.load(Ordering::Relaxed) {
+                            timed_out.store(true, Ordering::Relaxed);
+                        }
+                        break;
+                    }
+                    let run = next.fetch_add(1, Ordering::Relaxed);
+                    if run >= end.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let seed = derive_seed(checker.seed, first + run);
+                    if checker.explore(seed, config.steps_for_run(run)) {
+                        failing.lock().unwrap().push(run);
+                        if config.stop_on_first_failure {
+                            end.fetch_min(run + 1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+
+        // every run before both `next` and `end` was claimed before `end` was lowered past it
+        let runs = next.into_inner().min(end.into_inner());
+        let mut failing = failing.into_inner().unwrap();
+        failing.retain(|&run| run < runs);
+        failing.sort_unstable();
+        self.runs = first + runs;
+        report.runs = runs;
+        report.timed_out = timed_out.into_inner();
+        for run in failing {
+            if let Err(failed) = self.run_number(first + run, config.steps_for_run(run)) {
+                report.shrink_duration += failed.shrink_duration;
+                report.failures.push(failed);
+            }
+        }
+        report.passed = runs - report.failures.len() as u64;
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{Crash, ModelState, Timeout};
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::{Cell, RefCell},
+    fmt::{self, Debug},
+    mem,
+    panic::{self, catch_unwind, AssertUnwindSafe, PanicHookInfo},
+    sync::Once,
+    thread,
+};
+
+/// A failure observed while executing a trace. `E` is the error type of the model's steps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Failure<E> {
+    pub kind: FailureKind<E>,
+    pub message: String,
+    /// Whether `message` is the `&str` or `String` payload of a panic, rather than rendered by a
+    /// `PayloadFormatter` or a placeholder for a payload that cannot be shown.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub string_payload: bool,
+    /// Where the panic occurred, if it was reported to the panic hook.
+    pub location: Option<Location>,
+}
+
+impl<E: Debug> Failure<E> {
+    /// A failure of the given kind that was not raised by a panic in user code.
+    pub(crate) fn new(kind: FailureKind<E>) -> Self {
+        Self {
+            message: kind.to_string(),
+            kind,
+            string_payload: false,
+            location: None,
+        }
+    }
+}
+
+/// What caused a failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FailureKind<E> {
+    /// A step panicked.
+    Panic,
+    /// A step returned an error.
+    Error(E),
+    /// `ModelState::invariant` returned this error, after the initial state or a step.
+    Invariant(String),
+    /// The process executing the trace terminated, with `Isolation::Fork`.
+    Crash(Crash),
+    /// The process executing the trace exceeded a timeout, with `Isolation::Fork`.
+    Timeout(Timeout),
+}
+
+impl<E: Debug> fmt::Display for FailureKind<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Panic => write!(f, "panic"),
+            Self::Error(error) => write!(f, "step returned {error:?}"),
+            Self::Invariant(error) => write!(f, "invariant violated: {error}"),
+            Self::Crash(crash) => write!(f, "process {crash}"),
+            Self::Timeout(timeout) => write!(f, "{timeout}"),
+        }
+    }
+}
+
+/// Source location of a panic.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Decides whether a failure found while shrinking is the same bug as the original failure.
+/// Candidates that fail differently are rejected, so a trace for one bug cannot shrink into a
+/// trace for an unrelated one.
+#[derive(Default)]
+pub enum FailureIdentity<E> {
+    /// Any failure counts as the original one.
+    Any,
+    /// Failures must have the same message.
+    Message,
+    /// Panics must occur at the same location, or have the same message when either location is
+    /// unknown. Errors returned by steps must be the same bug, as judged by
+    /// `ModelState::same_error`. Invariant violations must report the same error,
+    /// crashes must terminate the process in the same way, timeouts must be the same, and other
+    /// failures must be of the same kind.
+    #[default]
+    Location,
+    /// Failures must map to the same key.
+    Classifier(fn(&Failure<E>) -> String),
+}
+
+impl<E> FailureIdentity<E> {
+    pub fn matches<M>(&self, original: &Failure<E>, candidate: &Failure<E>) -> bool
+    where
+        M: ModelState<Error = E>,
+    {
+        match self {
+            Self::Any => true,
+            Self::Message => original.message == candidate.message,
+            Self::Location => match (&original.kind, &candidate.kind) {
+                (FailureKind::Panic, FailureKind::Panic) => {
+                    match (&original.location, &candidate.location) {
+                        (Some(a), Some(b)) => a == b,
+                        _ => original.message == candidate.message,
+                    }
+                }
+                (FailureKind::Error(a), FailureKind::Error(b)) => M::same_error(a, b),
+                (FailureKind::Invariant(a), FailureKind::Invariant(b)) => a == b,
+                (FailureKind::Crash(a), FailureKind::Crash(b)) => a == b,
+                (FailureKind::Timeout(a), FailureKind::Timeout(b)) => a == b,
+                (a, b) => mem::discriminant(a) == mem::discriminant(b),
+            },
+            Self::Classifier(classify) => classify(original) == classify(candidate),
+        }
+    }
+}
+
+/// Whether two `Debug` renderings are of the same variant, judged by the identifier they start
+/// with. Renderings that do not start with an identifier, such as strings and numbers, must be
+/// equal.
+pub(crate) fn same_variant(a: &str, b: &str) -> bool {
+    fn variant(debug: &str) -> Option<&str> {
+        let end = debug
+            .find(|c: char| !c.is_alphanumeric() && c != '_' && c != ':')
+            .unwrap_or(debug.len());
+        let variant = &debug[..end];
+        variant
+            .starts_with(|c: char| c.is_alphabetic() || c == '_')
+            .then_some(variant)
+    }
+    match (variant(a), variant(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// not derived, to avoid requiring `E: Clone`
+impl<E> Clone for FailureIdentity<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E> Copy for FailureIdentity<E> {}
+
+impl<E> fmt::Debug for FailureIdentity<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any => write!(f, "Any"),
+            Self::Message => write!(f, "Message"),
+            Self::Location => write!(f, "Location"),
+            Self::Classifier(classify) => f.debug_tuple("Classifier").field(classify).finish(),
+        }
+    }
+}
+
+/// Renders a panic payload that is not a string as a failure message, returning `None` for
+/// payloads it does not recognize.
+pub type PayloadFormatter = fn(&(dyn Any + Send)) -> Option<String>;
+
+/// What happens to the output of the panic hook for panics caught by a checker. The default
+/// hook prints a message for each panic, and shrinking may execute hundreds of panicking traces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicOutput {
+    /// Call the previously installed panic hook for every panic.
+    Print,
+    /// Call the previously installed panic hook only for the panic of the shrunk trace, when it
+    /// is executed for the last time.
+    #[default]
+    Silent,
+    /// As `Silent`, but keep a message for each silenced panic in `FailedState::panic_output`.
+    Capture,
+}
+
+/// Settings of the panic hook and `catch`, scoped with `with_options`.
+#[derive(Clone, Copy)]
+pub(crate) struct Options {
+    pub formatter: Option<PayloadFormatter>,
+    pub output: PanicOutput,
+}
+
+/// What the panic hook records for the current thread.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Capture {
+    /// Not inside `catch`.
+    Off,
+    /// Record the panic location.
+    Location,
+    /// Also capture a backtrace, always if `force` is set, and otherwise as enabled by the
+    /// `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` environment variables.
+    Backtrace { force: bool },
+}
+
+thread_local! {
+    static CAPTURE: Cell<Capture> = const { Cell::new(Capture::Off) };
+    static LOCATION: RefCell<Option<Location>> = const { RefCell::new(None) };
+    static BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+    static OPTIONS: Cell<Options> = const {
+        Cell::new(Options {
+            formatter: None,
+            output: PanicOutput::Print,
+        })
+    };
+    static OUTPUT: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run `f`, converting a panic into a `Failure`.
+///
+/// The first call installs a process-wide panic hook that records panic locations on threads
+/// currently inside `catch`, and otherwise defers to the previously installed hook. Inside
+/// `catch`, the previous hook is only called with `PanicOutput::Print`.
+pub(crate) fn catch<R, E: Debug>(f: impl FnOnce() -> R) -> Result<R, Failure<E>> {
+    capture(Capture::Location, f).map_err(|(failure, _)| failure)
+}
+
+/// As `catch`, but also return a backtrace of the panic, if one is captured. Backtraces are
+/// always captured if `force` is set, and otherwise as enabled by `RUST_BACKTRACE`.
+pub(crate) fn catch_traced<R, E: Debug>(
+    force: bool,
+    f: impl FnOnce() -> R,
+) -> Result<R, (Failure<E>, Option<String>)> {
+    capture(Capture::Backtrace { force }, f)
+}
+
+fn capture<R, E: Debug>(
+    mode: Capture,
+    f: impl FnOnce() -> R,
+) -> Result<R, (Failure<E>, Option<String>)> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info: &PanicHookInfo| {
+            let capture = CAPTURE.with(Cell::get);
+            match capture {
+                Capture::Off => (),
+                Capture::Location => record(info, None),
+                Capture::Backtrace { force } => record(info, Some(force)),
+            }
+            let output = match capture {
+                Capture::Off => PanicOutput::Print,
+                _ => OPTIONS.with(Cell::get).output,
+            };
+            match output {
+                PanicOutput::Print => previous(info),
+                PanicOutput::Silent => (),
+                PanicOutput::Capture => OUTPUT.with(|o| o.borrow_mut().push(describe(info))),
+            }
+        }));
+    });
+
+    let outer = CAPTURE.with(|c| c.replace(mode));
+    LOCATION.with(|l| l.borrow_mut().take());
+    BACKTRACE.with(|b| b.borrow_mut().take());
+    let result = catch_unwind(AssertUnwindSafe(f));
+    CAPTURE.with(|c| c.set(outer));
+    result.map_err(|payload| {
+        let location = LOCATION.with(|l| l.borrow_mut().take());
+        let backtrace = BACKTRACE.with(|b| b.borrow_mut().take());
+        let failure = Failure {
+            kind: FailureKind::Panic,
+            message: extract_panic_payload(&*payload),
+            string_payload: payload.is::<&str>() || payload.is::<String>(),
+            location,
+        };
+        (failure, backtrace)
+    })
+}
+
+/// Run `f` with `options` applying to the panics caught on this thread.
+pub(crate) fn with_options<R>(options: Options, f: impl FnOnce() -> R) -> R {
+    let outer = OPTIONS.with(|c| c.replace(options));
+    // restore the outer options even if `f` panics
+    struct Restore(Options);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            OPTIONS.with(|c| c.set(self.0));
+        }
+    }
+    let _restore = Restore(outer);
+    f()
+}
+
+/// Take the messages of the panics silenced on this thread with `PanicOutput::Capture`.
+pub(crate) fn take_output() -> Vec<String> {
+    OUTPUT.with(|o| o.take())
+}
+
+fn record(info: &PanicHookInfo, backtrace: Option<bool>) {
+    // overwrite any earlier panic, which the step caught and recovered from
+    LOCATION.with(|l| {
+        *l.borrow_mut() = info.location().map(|location| Location {
+            file: location.file().to_owned(),
+            line: location.line(),
+            column: location.column(),
+        });
+    });
+    let backtrace = backtrace.map(|force| {
+        if force {
+            Backtrace::force_capture()
+        } else {
+            Backtrace::capture()
+        }
+    });
+    let backtrace = backtrace.filter(|b| b.status() == BacktraceStatus::Captured);
+    BACKTRACE.with(|b| *b.borrow_mut() = backtrace.map(|b| b.to_string()));
+}
+
+/// A message like the one printed by the default panic hook.
+fn describe(info: &PanicHookInfo) -> String {
+    let thread = thread::current();
+    let mut message = format!("thread '{}' panicked", thread.name().unwrap_or("<unnamed>"));
+    if let Some(location) = info.location() {
+        message += &format!(" at {location}");
+    }
+    message + ":\n" + &extract_panic_payload(info.payload())
+}
+
+fn extract_panic_payload(err: &(dyn Any + Send)) -> String {
+    if let Some(&s) = err.downcast_ref::<&str>() {
+        s.to_owned()
+    } else if let Some(s) = err.downcast_ref::<String>() {
+        s.to_owned()
+    } else if let Some(s) = (OPTIONS.with(Cell::get).formatter).and_then(|format| format(err)) {
+        s
+    } else {
+        "UNABLE TO SHOW RESULT OF PANIC.".to_owned()
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{Failure, FailureKind};
+use std::{
+    fmt::{self, Debug},
+    sync::atomic::{AtomicI32, Ordering},
+    time::Duration,
+};
+
+/// Where traces are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Isolation {
+    /// Execute traces in the checker's process. A step that terminates the process, e.g. by
+    /// overflowing its stack or calling `std::process::abort`, ends the test run without a
+    /// report.
+    #[default]
+    None,
+    /// Execute each trace, including candidate traces while shrinking, in a forked child process,
+    /// so that a step terminating the process fails the trace with `FailureKind::Crash` and can
+    /// be shrunk like any other failure. A trace that fails without crashing is executed again in
+    /// the checker's process to observe the failure, and so is a crashing trace up to the step
+    /// that crashed. Models must be deterministic for these executions to agree.
+    ///
+    /// A child process exceeding `ModelChecker::step_timeout` or `ModelChecker::run_timeout` is
+    /// killed, failing the trace with `FailureKind::Timeout`.
+    ///
+    /// Generating a trace is not shrunk, so a child process terminated while generating the
+    /// initial state or a step makes the checker panic with the seed of the run.
+    ///
+    /// Only supported on Unix.
+    Fork,
+}
+
+/// How a child process executing a trace terminated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Crash {
+    /// Killed by a signal, e.g. `SIGABRT` after `std::process::abort`, a panic with
+    /// `panic = "abort"` or a stack overflow, or `SIGSEGV` after an invalid memory access.
+    Signal(i32),
+    /// Exited with the given status, e.g. by calling `std::process::exit`.
+    Exit(i32),
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Signal(signal) => {
+                let name = match signal {
+                    4 => " (SIGILL)",
+                    6 => " (SIGABRT)",
+                    7 => " (SIGBUS)",
+                    8 => " (SIGFPE)",
+                    9 => " (SIGKILL)",
+                    11 => " (SIGSEGV)",
+                    _ => "",
+                };
+                write!(f, "killed by signal {signal}{name}")
+            }
+            Self::Exit(status) => write!(f, "exited with status {status}"),
+        }
+    }
+}
+
+/// A timeout exceeded by a child process executing a trace, with `Isolation::Fork`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Timeout {
+    /// Generating or executing a single step took longer than this. See
+    /// `ModelChecker::step_timeout`.
+    Step(Duration),
+    /// Executing the whole trace took longer than this. See `ModelChecker::run_timeout`.
+    Run(Duration),
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Step(timeout) => write!(f, "step timed out after {timeout:?}"),
+            Self::Run(timeout) => write!(f, "run timed out after {timeout:?}"),
+        }
+    }
+}
+
+/// Why a child process did not complete its trace.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Termination {
+    Crash(Crash),
+    Timeout(Timeout),
+}
+
+/// Write end of the pipe to the parent, in a child process.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Progress markers written by a child after its trace completes.
+const PASSED: u64 = u64::MAX;
+const FAILED: u64 = u64::MAX - 1;
+
+/// Points of a trace's execution: generating the initial state or a step, or executing a step.
+/// A child process reports each point to its parent as it reaches it, so the parent knows which
+/// point a crashing child reached last. The parent then executes the trace up to that point, and
+/// fails it there.
+pub(crate) struct Progress {
+    point: u64,
+    terminated: Option<(u64, Termination)>,
+}
+
+impl Progress {
+    pub fn new(terminated: Option<(u64, Termination)>) -> Self {
+        Self {
+            point: 0,
+            terminated,
+        }
+    }
+
+    /// Reach the next point, which fails as the child process did if it was terminated there.
+    pub fn next<E: Debug>(&mut self) -> Result<(), Failure<E>> {
+        if let Some((point, termination)) = self.terminated {
+            if point == self.point {
+                let kind = match termination {
+                    Termination::Crash(crash) => FailureKind::Crash(crash),
+                    Termination::Timeout(timeout) => FailureKind::Timeout(timeout),
+                };
+                return Err(Failure::new(kind));
+            }
+        }
+        report(self.point);
+        self.point += 1;
+        Ok(())
+    }
+}
+
+fn report(marker: u64) {
+    let fd = PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        #[cfg(unix)]
+        unix::write_marker(fd, marker);
+    }
+}
+
+/// Where and with which timeouts a checker executes traces.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Executor {
+    pub isolation: Isolation,
+    pub step_timeout: Option<Duration>,
+    pub run_timeout: Option<Duration>,
+}
+
+impl Executor {
+    /// Execute a trace with `run`, where `failed` tells whether the result of `run` is a
+    /// failure. `run` is called with the point at which a child process executing the trace was
+    /// terminated, if it was.
+    ///
+    /// Returns `None` if the trace passed in a child process, in which case it is not executed
+    /// in this process. Panics if a timeout is set without `Isolation::Fork`, since a hanging
+    /// step could not be stopped.
+    pub fn execute<T>(
+        &self,
+        mut run: impl FnMut(Option<(u64, Termination)>) -> T,
+        failed: impl Fn(&T) -> bool,
+    ) -> Option<T> {
+        match self.isolation {
+            Isolation::None => {
+                assert!(
+                    self.step_timeout.is_none() && self.run_timeout.is_none(),
+                    "timeouts are only supported with `Isolation::Fork`"
+                );
+                Some(run(None))
+            }
+            #[cfg(unix)]
+            Isolation::Fork => match unix::fork(self, || failed(&run(None))) {
+                Ok(false) => None,
+                Ok(true) => Some(run(None)),
+                Err(terminated) => Some(run(Some(terminated))),
+            },
+            #[cfg(not(unix))]
+            Isolation::Fork => {
+                let _ = failed;
+                panic!("`Isolation::Fork` is only supported on Unix")
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::{Crash, Executor, Termination, Timeout, FAILED, PASSED, PIPE};
+    use std::{
+        io,
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
+
+    /// Run `run` in a child process. Returns its result, or the last point reached and why the
+    /// child was terminated if it did not complete. A panic escaping `run` is reported as a
+    /// failure, so that it is raised again when the trace is executed in this process.
+    pub fn fork(
+        executor: &Executor,
+        run: impl FnOnce() -> bool,
+    ) -> Result<bool, (u64, Termination)> {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            panic!("failed to create pipe: {}", io::Error::last_os_error());
+        }
+        let [read, write] = fds;
+        // SAFETY: the child only executes the trace and exits, without returning to the caller
+        match unsafe { libc::fork() } {
+            -1 => panic!("failed to fork: {}", io::Error::last_os_error()),
+            0 => {
+                // SAFETY: `read` is open, and unused in the child
+                unsafe { libc::close(read) };
+                PIPE.store(write, Ordering::Relaxed);
+                let failed = catch_unwind(AssertUnwindSafe(run)).unwrap_or(true);
+                write_marker(write, if failed { FAILED } else { PASSED });
+                // SAFETY: exit without running destructors or `atexit` handlers, which belong to
+                // the parent
+                unsafe { libc::_exit(0) }
+            }
+            child => {
+                // SAFETY: `write` is open, and only used by the child
+                unsafe { libc::close(write) };
+                let (last, end) = watch(read, child, executor);
+                // SAFETY: `read` is open, and no longer used
+                unsafe { libc::close(read) };
+                let status = match end {
+                    End::Exited(status) => status,
+                    End::Completed => reap(child, 0).unwrap_or_default(),
+                    End::TimedOut(_) => {
+                        // SAFETY: `child` is a child of this process that has not been waited for
+                        unsafe { libc::kill(child, libc::SIGKILL) };
+                        reap(child, 0).unwrap_or_default()
+                    }
+                };
+                match (last, end) {
+                    (Some(PASSED), _) => Ok(false),
+                    (Some(FAILED), _) => Ok(true),
+                    (last, End::TimedOut(timeout)) => {
+                        Err((last.unwrap_or(0), Termination::Timeout(timeout)))
+                    }
+                    (last, _) => Err((last.unwrap_or(0), Termination::Crash(classify(status)))),
+                }
+            }
+        }
+    }
+
+    /// Wait for a child process with `waitpid` and `options`, returning its status, or `None`
+    /// if it has not exited and `options` includes `WNOHANG`.
+    fn reap(child: libc::pid_t, options: libc::c_int) -> Option<libc::c_int> {
+        let mut status = 0;
+        loop {
+            // SAFETY: `child` is a child of this process, and `status` is a valid pointer
+            match unsafe { libc::waitpid(child, &mut status, options) } {
+                0 => return None,
+                -1 => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        panic!("failed to wait for child process: {error}");
+                    }
+                }
+                _ => return Some(status),
+            }
+        }
+    }
+
+    fn classify(status: libc::c_int) -> Crash {
+        if libc::WIFSIGNALED(status) {
+            Crash::Signal(libc::WTERMSIG(status))
+        } else {
+            Crash::Exit(libc::WEXITSTATUS(status))
+        }
+    }
+
+    pub fn write_marker(fd: libc::c_int, marker: u64) {
+        let bytes = marker.to_le_bytes();
+        // SAFETY: `bytes` is valid for its length. A write of at most `PIPE_BUF` bytes to a pipe
+        // is atomic.
+        unsafe { libc::write(fd, bytes.as_ptr().cast(), bytes.len()) };
+    }
+
+    /// How watching a child process ended.
+    #[derive(Clone, Copy)]
+    enum End {
+        /// The child wrote its final marker.
+        Completed,
+        /// The child exited, with this status, before writing its final marker.
+        Exited(libc::c_int),
+        /// The child exceeded this timeout of the executor.
+        TimedOut(Timeout),
+    }
+
+    /// Interval at which the parent checks whether a silent child has exited. Reaching the end
+    /// of the pipe does not tell, since a process forked at the same time by another thread
+    /// inherits the pipe's write end and keeps it open.
+    const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Read markers written by `child` to `fd` until its final marker, until it exits, or until
+    /// a timeout of `executor` is exceeded, returning the last marker read. The step timeout
+    /// restarts at each marker.
+    fn watch(fd: libc::c_int, child: libc::pid_t, executor: &Executor) -> (Option<u64>, End) {
+        let start = Instant::now();
+        let mut step_start = start;
+        let mut last = None;
+        let mut exited = None;
+        let mut bytes = [0u8; 8];
+        let mut filled = 0;
+        loop {
+            if matches!(last, Some(PASSED | FAILED)) {
+                return (last, End::Completed);
+            }
+            let deadline = [
+                (executor.step_timeout).map(|t| (step_start + t, Timeout::Step(t))),
+                (executor.run_timeout).map(|t| (start + t, Timeout::Run(t))),
+            ];
+            let deadline = deadline.into_iter().flatten().min_by_key(|d| d.0);
+            let mut wait = EXIT_POLL_INTERVAL;
+            if let Some((deadline, exceeded)) = deadline {
+                let now = Instant::now();
+                if now >= deadline && exited.is_none() {
+                    return (last, End::TimedOut(exceeded));
+                }
+                wait = wait.min(deadline.saturating_duration_since(now));
+            }
+            if exited.is_some() {
+                // only read the markers written before the child exited
+                wait = Duration::ZERO;
+            }
+            match poll(fd, wait) {
+                Ok(true) => (),
+                Ok(false) => match exited {
+                    Some(status) => return (last, End::Exited(status)),
+                    None => {
+                        exited = reap(child, libc::WNOHANG);
+                        continue;
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => panic!("failed to poll child process: {e}"),
+            }
+            let rest = &mut bytes[filled..];
+            // SAFETY: `rest` is valid for its length
+            let n = unsafe { libc::read(fd, rest.as_mut_ptr().cast(), rest.len()) };
+            match n {
+                // every write end is closed, so the child has exited
+                0 => {
+                    let status = exited.or_else(|| reap(child, 0));
+                    return (last, End::Exited(status.unwrap_or_default()));
+                }
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                -1 => panic!(
+                    "failed to read from child process: {}",
+                    io::Error::last_os_error()
+                ),
+                n => filled += n as usize,
+            }
+            if filled == bytes.len() {
+                last = Some(u64::from_le_bytes(bytes));
+                step_start = Instant::now();
+                filled = 0;
+            }
+        }
+    }
+
+    /// Wait until `fd` can be read without blocking, for at most `timeout`. Returns false if the
+    /// timeout elapsed.
+    fn poll(fd: libc::c_int, timeout: Duration) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_nanos().div_ceil(1_000_000);
+        let millis = millis.min(libc::c_int::MAX as u128) as libc::c_int;
+        // SAFETY: `pollfd` is a valid pointer to one `pollfd`
+        match unsafe { libc::poll(&mut pollfd, 1, millis) } {
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(n > 0),
+        }
+    }
+}
@@ -1,127 +1,732 @@
+// lets derived code refer to `::modelcheck` within this crate
+extern crate self as modelcheck;
+
+mod arbitrary;
+mod choice;
+mod diff;
+mod differential;
+mod driver;
+mod failure;
+mod isolation;
+mod minimize;
+mod rng;
+mod settings;
+mod shrink;
+mod store;
+mod to_rust;
+
+pub use choice::Generation;
+pub use differential::{Differential, Mismatch, Reference, SystemUnderTest};
+pub use driver::{Config, RunReport};
+pub use failure::{Failure, FailureIdentity, FailureKind, Location, PanicOutput, PayloadFormatter};
+pub use isolation::{Crash, Isolation, Timeout};
+#[cfg(feature = "macros")]
+pub use modelcheck_macros::{modelcheck, Arbitrary, Shrink, ToRust};
 pub use rand;
-use rand::{rngs::SmallRng, SeedableRng as _};
+pub use rng::{DefaultRng, Gen, GenRange, Uniform};
+pub use shrink::Shrink;
+pub use store::{FailureStore, StoredCase};
+pub use to_rust::ToRust;
+
+use choice::{ChoiceMinimizer, ChoiceSource, ChoiceTrace};
+use isolation::{Executor, Progress, Termination};
+use minimize::Minimizer;
+use rand::{RngCore, SeedableRng as _};
 use std::{
-    any::Any,
-    fmt::Debug,
+    fmt::{self, Debug},
     marker::PhantomData,
-    panic::{catch_unwind, AssertUnwindSafe},
+    time::{Duration, Instant},
 };
 
 pub trait Arbitrary: 'static + Clone {
-    fn gen(rng: &mut SmallRng) -> Self;
+    fn gen(g: &mut Gen) -> Self;
 }
 
-pub trait ModelState: Arbitrary + Clone + Debug {
-    type Step: Arbitrary + Clone + Debug;
-    fn step(&mut self, step: Self::Step);
+pub trait ModelState: Arbitrary + Shrink + Clone + Debug {
+    type Step: Arbitrary + Shrink + Clone + Debug;
+    /// Error returned by a failing step. Models whose steps only fail by panicking can use
+    /// `std::convert::Infallible`.
+    type Error: Clone + Debug + 'static;
+
+    /// Apply `step`, failing by returning an error or by panicking.
+    fn step(&mut self, step: Self::Step) -> Result<(), Self::Error>;
+
+    /// Generate the next step to apply to the current state. A panic here, or in `precondition`
+    /// while a step is generated, is not a failure of the trace and propagates out of the
+    /// checker, as one in `Arbitrary::gen` does.
+    fn gen_step(&self, g: &mut Gen) -> Self::Step {
+        Self::Step::gen(g)
+    }
+
+    /// Whether `step` may be applied to the current state. Generated steps are retried until they
+    /// satisfy the precondition, and shrinking never produces a trace containing a step that does
+    /// not satisfy it at its position.
+    fn precondition(&self, _step: &Self::Step) -> bool {
+        true
+    }
+
+    /// Check invariants that must hold for every state. This is called for the initial state and
+    /// after each step, and an error fails the trace with `FailureKind::Invariant`.
+    fn invariant(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether two errors returned by steps are the same bug, so that shrinking with
+    /// `FailureIdentity::Location` may replace one with the other. By default, errors must be the
+    /// same variant, judged by the identifier their `Debug` output starts with, or have the same
+    /// `Debug` output if it does not start with one.
+    fn same_error(a: &Self::Error, b: &Self::Error) -> bool {
+        failure::same_variant(&format!("{a:?}"), &format!("{b:?}"))
+    }
 }
 
+/// Number of attempts to generate a step satisfying `ModelState::precondition` before a trace is
+/// ended early.
+const GEN_STEP_ATTEMPTS: usize = 100;
+
 pub struct ModelChecker<M: ModelState> {
-    rng: SmallRng,
-    _m: PhantomData<M>,
+    seed: u64,
+    runs: u64,
+    identity: FailureIdentity<M::Error>,
+    generation: Generation,
+    size: usize,
+    store: Option<FailureStore>,
+    formatter: Option<PayloadFormatter>,
+    output: PanicOutput,
+    backtraces: bool,
+    executor: Executor,
+    // a checker only creates states while it runs, so it can be shared between threads
+    _m: PhantomData<fn() -> M>,
 }
 
 impl<M: ModelState> Default for ModelChecker<M> {
+    /// A checker with the master seed given by `MODELCHECK_SEED` or `modelcheck.toml` (see
+    /// `Config::with_env`), or a random one otherwise.
     fn default() -> Self {
-        Self {
-            rng: SmallRng::from_entropy(),
-            _m: PhantomData,
-        }
+        let seed = settings::Settings::load().seed;
+        Self::with_seed(seed.unwrap_or_else(|| DefaultRng::from_entropy().next_u64()))
     }
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "M: serde::Serialize, M::Step: serde::Serialize, \
+                     M::Error: serde::Serialize",
+        deserialize = "M: serde::Deserialize<'de>, M::Step: serde::Deserialize<'de>, \
+                       M::Error: serde::Deserialize<'de>",
+    ))
+)]
 pub struct FailedState<M: ModelState> {
+    /// The initial state, shrunk along with `steps`.
     pub state: M,
     pub steps: Vec<M::Step>,
-    pub error: String,
+    /// The `{:#?}` rendering of the initial state, followed by that of the state after each
+    /// step. The last entry is the state as left by the failing step, or before it if it crashed
+    /// the process or timed out.
+    pub history: Vec<String>,
+    /// The failure produced by the shrunk trace.
+    pub failure: Failure<M::Error>,
+    /// Backtrace of the panic of the failing step, captured when the shrunk trace is executed
+    /// for the last time. See `ModelChecker::backtraces`.
+    pub backtrace: Option<String>,
+    /// Messages of the panics silenced with `PanicOutput::Capture` during the run, including
+    /// those of candidate traces while shrinking.
+    pub panic_output: Vec<String>,
+    /// The failure produced by the trace before shrinking. It matches `failure` under the
+    /// checker's `FailureIdentity`.
+    pub original_failure: Failure<M::Error>,
+    /// Seed of the failing run. Passing it to `ModelChecker::run_seed` with the same `max_steps`
+    /// regenerates the original (unshrunk) trace.
+    pub seed: u64,
+    /// Index of the failing run within its checker, counting from 0.
+    pub run: u64,
+    pub max_steps: usize,
+    /// Number of candidate traces executed while shrinking.
+    pub shrink_replays: usize,
+    /// Wall-clock time spent shrinking.
+    pub shrink_duration: Duration,
+}
+
+impl<M: ModelState> FailedState<M> {
+    /// The step that failed, which is always the last step of the trace, or `None` if the initial
+    /// state failed.
+    pub fn failing_step(&self) -> Option<&M::Step> {
+        self.steps.last()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<M: ModelState> FailedState<M>
+where
+    M: serde::Serialize,
+    M::Step: serde::Serialize,
+    M::Error: serde::Serialize,
+{
+    /// Write the failure to `path` as JSON, e.g. to attach it to a bug report.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, self).map_err(std::io::Error::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<M: ModelState> FailedState<M>
+where
+    M: serde::de::DeserializeOwned,
+    M::Step: serde::de::DeserializeOwned,
+    M::Error: serde::de::DeserializeOwned,
+{
+    /// Read a failure written by `save`. The trace can then be executed with
+    /// `ModelChecker::replay`.
+    pub fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        serde_json::from_reader(file).map_err(std::io::Error::from)
+    }
+}
+
+impl<M: ModelState> fmt::Display for FailedState<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed")?;
+        if let Some(location) = &self.failure.location {
+            write!(f, " at {location}")?;
+        }
+        writeln!(f, ":\n{}", self.failure.message)?;
+        writeln!(f, "initial state: {:#?}", self.state)?;
+        writeln!(f, "steps:")?;
+        for (index, step) in self.steps.iter().enumerate() {
+            writeln!(f, "  {index}: {step:?}")?;
+            if let [before, after] = self.history.get(index..index + 2).unwrap_or_default() {
+                match diff::diff(before, after) {
+                    Some(diff) if diff.is_empty() => writeln!(f, "    (state unchanged)")?,
+                    Some(diff) => {
+                        for line in diff.lines() {
+                            writeln!(f, "    {line}")?;
+                        }
+                    }
+                    // too many changes to diff, so show the whole state
+                    None => {
+                        for line in after.lines() {
+                            writeln!(f, "    {line}")?;
+                        }
+                    }
+                }
+            }
+        }
+        write!(f, "shrunk with {} replays", self.shrink_replays)?;
+        if self.original_failure.message != self.failure.message {
+            write!(
+                f,
+                ", from a trace failing with:\n{}",
+                self.original_failure.message
+            )?;
+        }
+        writeln!(f)?;
+        write!(
+            f,
+            "reproduce with: `checker.run_seed({:#x}, {})` on a checker configured as this one",
+            self.seed, self.max_steps
+        )?;
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\nbacktrace:\n{backtrace}")?;
+        }
+        Ok(())
+    }
 }
 
 impl<M: ModelState> ModelChecker<M> {
+    /// Create a checker whose runs are derived deterministically from `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            runs: 0,
+            identity: FailureIdentity::default(),
+            generation: Generation::default(),
+            size: Gen::DEFAULT_SIZE,
+            store: None,
+            formatter: None,
+            output: PanicOutput::default(),
+            backtraces: false,
+            executor: Executor::default(),
+            _m: PhantomData,
+        }
+    }
+
+    /// Set how failures found while shrinking are matched against the original failure.
+    pub fn failure_identity(mut self, identity: FailureIdentity<M::Error>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Set how traces are generated and shrunk.
+    pub fn generation(mut self, generation: Generation) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    /// Set the size of the `Gen` passed to generators, which bounds the length of typical
+    /// generated collections.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Save failing runs to `store`, and replay its runs of this model in `check`.
+    pub fn failure_store(mut self, store: FailureStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Set how panic payloads other than strings are rendered as failure messages.
+    pub fn panic_formatter(mut self, formatter: PayloadFormatter) -> Self {
+        self.formatter = Some(formatter);
+        self
+    }
+
+    /// Set what happens to the panic hook output of panicking traces.
+    pub fn panic_output(mut self, output: PanicOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Always capture a backtrace of a panic in the shrunk trace. Otherwise, one is captured only
+    /// if enabled by the `RUST_BACKTRACE` environment variable.
+    pub fn backtraces(mut self, enabled: bool) -> Self {
+        self.backtraces = enabled;
+        self
+    }
+
+    /// Set where traces are executed, to catch steps that terminate the process.
+    pub fn isolation(mut self, isolation: Isolation) -> Self {
+        self.executor.isolation = isolation;
+        self
+    }
+
+    /// Fail a trace with `Timeout::Step` if generating or executing one of its steps takes
+    /// longer than `timeout`. Requires `Isolation::Fork`, where the hanging child process is
+    /// killed; executing a trace panics otherwise.
+    pub fn step_timeout(mut self, timeout: Duration) -> Self {
+        self.executor.step_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail a trace with `Timeout::Run` if executing it takes longer than `timeout`. Each
+    /// candidate trace executed while shrinking is timed separately. Requires `Isolation::Fork`,
+    /// where the hanging child process is killed; executing a trace panics otherwise.
+    pub fn run_timeout(mut self, timeout: Duration) -> Self {
+        self.executor.run_timeout = Some(timeout);
+        self
+    }
+
+    /// The master seed from which every run seed is derived.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Execute the next run, saving it to the checker's `FailureStore` if it fails. Panics if the
+    /// failure cannot be saved.
+    // `FailedState` is returned once per failing run, so its size is not a concern
+    #[allow(clippy::result_large_err)]
     pub fn run(&mut self, max_steps: usize) -> Result<(), FailedState<M>> {
-        let state = M::gen(&mut self.rng);
-        let mut steps: Vec<M::Step> = (0..max_steps)
-            .map(|_| M::Step::gen(&mut self.rng))
-            .collect();
-
-        let result = Self::run_steps(state.clone(), &steps);
-        let (mut last_error, failed_step) = match result {
-            Ok(()) => return Ok(()),
-            Err((error, failed_step)) => (error, failed_step),
-        };
+        let run = self.runs;
+        self.runs += 1;
+        self.run_number(run, max_steps)
+    }
 
-        // shrink steps
-        steps.truncate(failed_step + 1);
-        assert!(!steps.is_empty());
-        let mut index = 0;
-        for _ in 0..steps.len() {
-            let mut shrink_steps = steps.clone();
-            shrink_steps.remove(index);
-            match Self::run_steps(state.clone(), &shrink_steps) {
-                Ok(()) => {
-                    index += 1;
-                    continue;
-                }
-                Err((error, _)) => {
-                    last_error = error;
-                    steps = shrink_steps;
+    /// Execute the `run`th run, saving it to the checker's `FailureStore` if it fails.
+    #[allow(clippy::result_large_err)]
+    fn run_number(&mut self, run: u64, max_steps: usize) -> Result<(), FailedState<M>> {
+        self.run_seed(derive_seed(self.seed, run), max_steps)
+            .map_err(|failed| {
+                let failed = FailedState { run, ..failed };
+                if let Some(store) = &self.store {
+                    store.save(&failed).unwrap_or_else(|e| {
+                        panic!("failed to save to {}: {e}", store.path::<M>().display())
+                    });
                 }
-            };
+                failed
+            })
+    }
+
+    /// Replay the run that produced `failed`, including shrinking.
+    #[allow(clippy::result_large_err)]
+    pub fn reproduce(&mut self, failed: &FailedState<M>) -> Result<(), FailedState<M>> {
+        self.run_seed(failed.seed, failed.max_steps)
+            .map_err(|replayed| FailedState {
+                run: failed.run,
+                ..replayed
+            })
+    }
+
+    /// Execute the shrunk trace of `failed` step by step against the current code, without
+    /// generating or shrinking. Unlike `reproduce`, this does not depend on the model's
+    /// generators, so it works for a trace loaded with `FailedState::load` after they change.
+    /// Execution stops without failing at a step that does not satisfy
+    /// `ModelState::precondition`.
+    pub fn replay(&self, failed: &FailedState<M>) -> Result<(), Failure<M::Error>> {
+        let run = |terminated| {
+            failure::with_options(self.options(PanicOutput::Print), || {
+                Self::run_steps(failed.state.clone(), &failed.steps, terminated, |_| ())
+            })
+        };
+        self.executor
+            .execute(run, Result::is_err)
+            .unwrap_or(Ok(()))
+            .map_err(|(failure, _)| failure)
+    }
+
+    /// Execute a single run generated from `seed`, independent of the checker's master seed.
+    #[allow(clippy::result_large_err)]
+    pub fn run_seed(&mut self, seed: u64, max_steps: usize) -> Result<(), FailedState<M>> {
+        failure::take_output();
+        failure::with_options(self.options(self.output), || match self.generation {
+            Generation::Random => self.run_random(seed, max_steps),
+            Generation::Choices => self.run_choices(seed, max_steps),
+        })
+        .map_err(|failed| FailedState {
+            seed,
+            panic_output: failure::take_output(),
+            ..failed
+        })
+    }
+
+    /// Whether the run generated from `seed` fails, without shrinking it.
+    fn explore(&self, seed: u64, max_steps: usize) -> bool {
+        failure::with_options(self.options(self.output), || match self.generation {
+            Generation::Random => {
+                matches!(self.execute_random(seed, max_steps), Some((_, _, Err(_))))
+            }
+            Generation::Choices => {
+                matches!(self.execute_choices(seed, max_steps), Some((_, Err(_))))
+            }
+        })
+    }
+
+    fn options(&self, output: PanicOutput) -> failure::Options {
+        failure::Options {
+            formatter: self.formatter,
+            output,
         }
+    }
+
+    /// Generate and execute a trace with `Generation::Random`. Returns `None` if it passed in a
+    /// child process.
+    fn execute_random(&self, seed: u64, max_steps: usize) -> Option<Trace<M>> {
+        let mut rng = DefaultRng::seed_from_u64(seed);
+        let trace = self.executor.execute(
+            |terminated| Self::generate(&mut rng, max_steps, self.size, terminated, |_| ()),
+            |trace| !matches!(trace, Ok((_, _, Ok(())))),
+        )?;
+        Some(trace.unwrap_or_else(|failure| Self::generation_terminated(seed, failure)))
+    }
+
+    /// Generate and execute a trace with `Generation::Choices`. Returns `None` if it passed in a
+    /// child process.
+    fn execute_choices(
+        &self,
+        seed: u64,
+        max_steps: usize,
+    ) -> Option<(ChoiceTrace<M>, Outcome<M::Error>)> {
+        let rng = DefaultRng::seed_from_u64(seed);
+        let trace = self.executor.execute(
+            |terminated| {
+                let source = ChoiceSource::record(rng.clone());
+                ChoiceTrace::<M>::generate(source, max_steps, self.size, terminated)
+            },
+            |trace| !matches!(trace, Ok((_, Ok(())))),
+        )?;
+        Some(trace.unwrap_or_else(|failure| Self::generation_terminated(seed, failure)))
+    }
+
+    /// Raise the failure of a child process that was terminated while generating the trace of
+    /// `seed`. Generation is not shrunk, as a panic in it is not.
+    fn generation_terminated(seed: u64, failure: Failure<M::Error>) -> ! {
+        panic!(
+            "generating the trace of seed {seed:#x} failed in the child process: {}",
+            failure.message
+        )
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn run_random(&self, seed: u64, max_steps: usize) -> Result<(), FailedState<M>> {
+        let Some((state, steps, Err((failure, _)))) = self.execute_random(seed, max_steps) else {
+            return Ok(());
+        };
+
+        let start = Instant::now();
+        let mut minimizer = Minimizer::new(state, steps, failure, self.identity, self.executor);
+        minimizer.minimize();
+        let (history, backtrace) =
+            self.history(&minimizer.state, &minimizer.steps, &minimizer.failure);
 
         Err(FailedState {
-            state,
-            steps,
-            error: last_error,
+            state: minimizer.state,
+            steps: minimizer.steps,
+            history,
+            failure: minimizer.failure,
+            backtrace,
+            panic_output: Vec::new(),
+            original_failure: minimizer.original,
+            seed: 0,
+            run: 0,
+            max_steps,
+            shrink_replays: minimizer.replays,
+            shrink_duration: start.elapsed(),
+        })
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn run_choices(&self, seed: u64, max_steps: usize) -> Result<(), FailedState<M>> {
+        let Some((trace, Err((failure, _)))) = self.execute_choices(seed, max_steps) else {
+            return Ok(());
+        };
+
+        let start = Instant::now();
+        let mut minimizer = ChoiceMinimizer::new(
+            trace,
+            failure,
+            self.identity,
+            max_steps,
+            self.size,
+            self.executor,
+        );
+        minimizer.minimize();
+        let (history, backtrace) = self.history(
+            &minimizer.trace.state,
+            &minimizer.trace.steps,
+            &minimizer.failure,
+        );
+
+        Err(FailedState {
+            state: minimizer.trace.state,
+            steps: minimizer.trace.steps,
+            history,
+            failure: minimizer.failure,
+            backtrace,
+            panic_output: Vec::new(),
+            original_failure: minimizer.original,
+            seed: 0,
+            run: 0,
+            max_steps,
+            shrink_replays: minimizer.replays,
+            shrink_duration: start.elapsed(),
         })
     }
 
-    fn run_steps(mut state: M, steps: &[M::Step]) -> Result<(), (String, usize)> {
+    /// Generate an initial state and up to `max_steps` steps, executing each step as it is
+    /// generated so that `ModelState::gen_step` sees the current state.
+    ///
+    /// `mark` is called with the random source after the initial state and after each step is
+    /// generated. `terminated` is the point at which a child process executing the trace was
+    /// terminated, if it was.
+    ///
+    /// A panic in `ModelState::gen_step` or in `ModelState::precondition` of a step being
+    /// generated is not a failure of the trace, which could not be reproduced by executing its
+    /// steps, and propagates to the caller as a panic in `Arbitrary::gen` does. Likewise, if the
+    /// child process was terminated while generating the initial state or a step, its failure
+    /// is returned as `Err` rather than as the result of the trace.
+    fn generate<R: RngCore>(
+        rng: &mut R,
+        max_steps: usize,
+        size: usize,
+        terminated: Option<(u64, Termination)>,
+        mut mark: impl FnMut(&R),
+    ) -> Generated<Trace<M>, M::Error> {
+        let mut progress = Progress::new(terminated);
+        progress.next()?;
+        let initial = M::gen(&mut Gen::with_size(rng, size));
+        mark(rng);
+        let mut state = initial.clone();
+        let mut steps = Vec::new();
+        let result = Self::generate_steps(
+            &mut state,
+            &mut steps,
+            rng,
+            max_steps,
+            size,
+            &mut progress,
+            mark,
+        )?;
+        let executed = steps.len();
+        Ok((
+            initial,
+            steps,
+            result.map_err(|failure| (failure, executed)),
+        ))
+    }
+
+    /// Generate and execute up to `max_steps` steps from `state`, for `generate`. Only executing
+    /// a step and checking the invariant are caught as failures, and their result is returned as
+    /// `Ok`. Returns `Err` if the child process was terminated while generating a step.
+    fn generate_steps<R: RngCore>(
+        state: &mut M,
+        steps: &mut Vec<M::Step>,
+        rng: &mut R,
+        max_steps: usize,
+        size: usize,
+        progress: &mut Progress,
+        mut mark: impl FnMut(&R),
+    ) -> Generated<Result<(), Failure<M::Error>>, M::Error> {
+        let checked = progress
+            .next()
+            .and_then(|()| failure::catch(|| Self::check_invariant(state)))
+            .and_then(|result| result);
+        if checked.is_err() {
+            return Ok(checked);
+        }
+        for _ in 0..max_steps {
+            progress.next()?;
+            let step = (0..GEN_STEP_ATTEMPTS)
+                .map(|_| state.gen_step(&mut Gen::with_size(rng, size)))
+                .find(|step| state.precondition(step));
+            let Some(step) = step else { break };
+            mark(rng);
+            steps.push(step.clone());
+            let executed = progress
+                .next()
+                .and_then(|()| {
+                    failure::catch(|| {
+                        state.step(step).map_err(Self::step_error)?;
+                        Self::check_invariant(state)
+                    })
+                })
+                .and_then(|result| result);
+            if executed.is_err() {
+                return Ok(executed);
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// The `Debug` rendering of `state`, and of the state after each step of a failing trace,
+    /// with a backtrace of the panic of the failing step if one is captured. The panic is
+    /// always reported to the previous panic hook, so that it is printed once per failing run.
+    /// A step that crashed a child process or timed out is not executed.
+    fn history(
+        &self,
+        state: &M,
+        steps: &[M::Step],
+        failure: &Failure<M::Error>,
+    ) -> (Vec<String>, Option<String>) {
+        let steps = match failure.kind {
+            FailureKind::Crash(_) | FailureKind::Timeout(_) => {
+                &steps[..steps.len().saturating_sub(1)]
+            }
+            _ => steps,
+        };
+        let options = self.options(PanicOutput::Print);
+        failure::with_options(options, || self.replay_history(state, steps))
+    }
+
+    fn replay_history(&self, state: &M, steps: &[M::Step]) -> (Vec<String>, Option<String>) {
+        let mut state = state.clone();
+        let mut history = vec![format!("{state:#?}")];
+        let mut backtrace = None;
+        for step in steps {
+            // the last step fails, possibly by panicking
+            let result =
+                failure::catch_traced::<_, M::Error>(self.backtraces, || state.step(step.clone()));
+            if let Err((_, traced)) = result {
+                backtrace = traced;
+            }
+            history.push(format!("{state:#?}"));
+        }
+        (history, backtrace)
+    }
+
+    /// Execute `steps` from `state`. Execution stops without failing at the first step
+    /// that does not satisfy `ModelState::precondition`. `terminated` is as for `generate`.
+    ///
+    /// `after_step` is called with the state after each step that satisfies the invariant.
+    fn run_steps(
+        mut state: M,
+        steps: &[M::Step],
+        terminated: Option<(u64, Termination)>,
+        mut after_step: impl FnMut(&M),
+    ) -> Outcome<M::Error> {
+        let mut progress = Progress::new(terminated);
         let mut last_step = 0;
-        catch_unwind(AssertUnwindSafe(|| {
+        failure::catch(|| {
+            progress.next()?;
+            Self::check_invariant(&state)?;
             for step in steps {
                 last_step += 1;
-                state.step(step.clone());
+                progress.next()?;
+                if !state.precondition(step) {
+                    break;
+                }
+                state.step(step.clone()).map_err(Self::step_error)?;
+                Self::check_invariant(&state)?;
+                after_step(&state);
             }
-        }))
-        .map_err(Self::extract_panic_payload)
-        .map_err(|error| (error, last_step))
+            Ok(())
+        })
+        .and_then(|result| result)
+        .map_err(|failure| (failure, last_step))
     }
 
-    fn extract_panic_payload(err: Box<dyn Any + Send>) -> String {
-        if let Some(&s) = err.downcast_ref::<&str>() {
-            s.to_owned()
-        } else if let Some(s) = err.downcast_ref::<String>() {
-            s.to_owned()
-        } else {
-            "UNABLE TO SHOW RESULT OF PANIC.".to_owned()
-        }
+    fn step_error(error: M::Error) -> Failure<M::Error> {
+        Failure::new(FailureKind::Error(error))
+    }
+
+    fn check_invariant(state: &M) -> Result<(), Failure<M::Error>> {
+        state
+            .invariant()
+            .map_err(|error| Failure::new(FailureKind::Invariant(error)))
     }
 }
 
+/// Result of executing a trace. On failure, holds the number of steps executed, including the
+/// failing one.
+type Outcome<E> = Result<(), (Failure<E>, usize)>;
+
+/// Result of generating a trace, failing if a child process was terminated while generating it.
+type Generated<T, E> = Result<T, Failure<E>>;
+
+/// A generated initial state and steps, and the result of executing them.
+type Trace<M> = (
+    M,
+    Vec<<M as ModelState>::Step>,
+    Outcome<<M as ModelState>::Error>,
+);
+
+/// Seed of the `run`th run of a checker with the given master seed: the `run`th output of a
+/// SplitMix64 stream starting at `seed`.
+fn derive_seed(seed: u64, run: u64) -> u64 {
+    let mut state = seed.wrapping_add(run.wrapping_mul(0x9e3779b97f4a7c15));
+    rng::splitmix64(&mut state)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use rand::Rng as _;
+    use std::convert::Infallible;
 
     #[derive(Clone, Debug)]
     struct TestModel;
     #[derive(Clone, Debug)]
     struct TestStep(bool);
+    impl Shrink for TestModel {}
+    impl Shrink for TestStep {}
     impl Arbitrary for TestModel {
-        fn gen(_: &mut SmallRng) -> Self {
+        fn gen(_: &mut Gen) -> Self {
             Self
         }
     }
     impl Arbitrary for TestStep {
-        fn gen(rng: &mut SmallRng) -> Self {
-            Self(rng.gen_bool(0.5))
+        fn gen(g: &mut Gen) -> Self {
+            Self(g.gen_bool(0.5))
         }
     }
     impl ModelState for TestModel {
         type Step = TestStep;
-        fn step(&mut self, step: Self::Step) {
+        type Error = Infallible;
+        fn step(&mut self, step: Self::Step) -> Result<(), Infallible> {
             assert!(step.0);
+            Ok(())
         }
     }
 
@@ -136,4 +741,982 @@ mod test {
                 .unwrap_or_else(|fail| { fail.steps.iter().filter(|step| !step.0).count() == 1 }));
         }
     }
+
+    #[test]
+    fn check_config() {
+        let config = Config {
+            runs: 5,
+            min_steps: 2,
+            max_steps: 10,
+            ..Config::default()
+        };
+        let lengths: Vec<usize> = (0..5).map(|run| config.steps_for_run(run)).collect();
+        assert_eq!(lengths, [2, 4, 6, 8, 10]);
+
+        let mut checker = ModelChecker::<TestModel>::default();
+        let report = checker.check(&Config {
+            runs: 10,
+            min_steps: 3,
+            max_steps: 3,
+            stop_on_first_failure: false,
+            ..Config::default()
+        });
+        assert_eq!(report.runs, 10);
+        assert_eq!(report.passed + report.failures.len() as u64, 10);
+        for fail in &report.failures {
+            assert_eq!(fail.steps.iter().filter(|step| !step.0).count(), 1);
+        }
+
+        let mut checker = ModelChecker::<TestModel>::with_seed(3);
+        let report = checker.check(&Config {
+            runs: 1000,
+            max_steps: 64,
+            ..Config::default()
+        });
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.passed + 1, report.runs);
+        assert_eq!(report.failure().unwrap().run, report.runs - 1);
+
+        let report = checker.check(&Config {
+            time_budget: Some(Duration::ZERO),
+            ..Config::default()
+        });
+        assert!(report.timed_out && report.runs == 0 && report.is_ok());
+    }
+
+    #[test]
+    fn parallel() {
+        for stop_on_first_failure in [true, false] {
+            let config = Config {
+                runs: 200,
+                max_steps: 64,
+                stop_on_first_failure,
+                ..Config::default()
+            };
+            let sequential = ModelChecker::<TestModel>::with_seed(3).check(&config);
+            let mut checker = ModelChecker::<TestModel>::with_seed(3);
+            let parallel = checker.check(&Config {
+                threads: 4,
+                ..config
+            });
+            assert_eq!(parallel.runs, sequential.runs);
+            assert_eq!(parallel.passed, sequential.passed);
+            let runs = |report: &RunReport<TestModel>| {
+                let failures = report.failures.iter();
+                failures
+                    .map(|failed| (failed.run, failed.seed))
+                    .collect::<Vec<_>>()
+            };
+            assert!(!parallel.is_ok());
+            assert_eq!(runs(&parallel), runs(&sequential));
+            assert_eq!(checker.runs, parallel.runs);
+        }
+    }
+
+    #[test]
+    fn reproduce_seed() {
+        let mut checker = ModelChecker::<TestModel>::with_seed(7);
+        let fail = (0..100).find_map(|_| checker.run(8).err()).unwrap();
+        let replayed = checker.reproduce(&fail).unwrap_err();
+        assert_eq!((replayed.seed, replayed.run), (fail.seed, fail.run));
+        assert_eq!(format!("{:?}", replayed.steps), format!("{:?}", fail.steps));
+        assert_eq!(replayed.failure, fail.failure);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Threshold(u32);
+    impl Arbitrary for Threshold {
+        fn gen(g: &mut Gen) -> Self {
+            Self(g.gen_range(100..1000))
+        }
+    }
+    impl Shrink for Threshold {
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            Box::new(self.0.shrink().map(Self))
+        }
+    }
+    impl ModelState for Threshold {
+        type Step = (u32, String);
+        type Error = Infallible;
+        fn step(&mut self, (n, s): Self::Step) -> Result<(), Infallible> {
+            assert!(n < self.0 || s.is_empty(), "over threshold");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shrink_values() {
+        let mut checker = ModelChecker::<Threshold>::with_seed(3);
+        let fail = (0..100).find_map(|_| checker.run(16).err()).unwrap();
+        assert_eq!(fail.state.0, 0);
+        assert_eq!(fail.steps.len(), 1);
+        assert_eq!(fail.steps[0], (0, "a".to_owned()));
+        assert_eq!(fail.failure.message, "over threshold");
+    }
+
+    #[derive(Clone, Debug)]
+    struct Pair(Vec<u32>);
+    impl Arbitrary for Pair {
+        fn gen(_: &mut Gen) -> Self {
+            Self(Vec::new())
+        }
+    }
+    impl Shrink for Pair {}
+    impl ModelState for Pair {
+        type Step = u32;
+        type Error = Infallible;
+        fn step(&mut self, step: u32) -> Result<(), Infallible> {
+            self.0.push(step);
+            assert!(!(self.0.contains(&1) && self.0.contains(&2)));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ddmin_long_trace() {
+        let state = Pair(Vec::new());
+        let mut steps: Vec<u32> = (3..3000).collect();
+        steps.insert(700, 2);
+        steps.insert(2500, 1);
+        let failure = ModelChecker::run_steps(state.clone(), &steps, None, |_| ())
+            .unwrap_err()
+            .0;
+        let mut minimizer = Minimizer::new(
+            state,
+            steps,
+            failure,
+            FailureIdentity::Any,
+            Executor::default(),
+        );
+        minimizer.minimize();
+        assert_eq!(minimizer.steps, vec![2, 1]);
+        assert!(minimizer.replays < 500, "{} replays", minimizer.replays);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Long(usize);
+    impl Arbitrary for Long {
+        fn gen(_: &mut Gen) -> Self {
+            Self(0)
+        }
+    }
+    impl Shrink for Long {}
+    thread_local! {
+        static LONG_STEPS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+    impl ModelState for Long {
+        type Step = u8;
+        type Error = Infallible;
+        fn step(&mut self, _: u8) -> Result<(), Infallible> {
+            LONG_STEPS.set(LONG_STEPS.get() + 1);
+            self.0 += 1;
+            assert!(self.0 < 64, "too long");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn checkpoints() {
+        let steps = vec![200; 64];
+        let failure = ModelChecker::run_steps(Long(0), &steps, None, |_| ())
+            .unwrap_err()
+            .0;
+        let mut minimizer = Minimizer::new(
+            Long(0),
+            steps,
+            failure,
+            FailureIdentity::default(),
+            Executor::default(),
+        );
+        LONG_STEPS.set(0);
+        let options = failure::Options {
+            formatter: None,
+            output: PanicOutput::Silent,
+        };
+        failure::with_options(options, || minimizer.minimize());
+        assert_eq!(minimizer.steps, [0; 64]);
+        let executed = LONG_STEPS.get();
+        // each candidate shares a prefix with the trace; executing every candidate from the
+        // initial state takes 19456 steps
+        assert!(executed < 16_000, "{executed} steps executed");
+    }
+
+    #[derive(Clone, Debug)]
+    struct TwoBugs(usize);
+    impl Arbitrary for TwoBugs {
+        fn gen(_: &mut Gen) -> Self {
+            Self(0)
+        }
+    }
+    impl Shrink for TwoBugs {}
+    impl ModelState for TwoBugs {
+        type Step = u32;
+        type Error = Infallible;
+        fn step(&mut self, step: u32) -> Result<(), Infallible> {
+            self.0 += 1;
+            assert!(self.0 != 1 || step != 0, "bug b");
+            assert!(self.0 < 3 || step != 0, "bug a");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn no_slippage() {
+        let steps = vec![5, 5, 0];
+        let failure = ModelChecker::run_steps(TwoBugs(0), &steps, None, |_| ())
+            .unwrap_err()
+            .0;
+        assert_eq!(failure.message, "bug a");
+
+        let mut minimizer = Minimizer::new(
+            TwoBugs(0),
+            steps.clone(),
+            failure.clone(),
+            FailureIdentity::default(),
+            Executor::default(),
+        );
+        minimizer.minimize();
+        assert_eq!(minimizer.steps, vec![1, 0, 0]);
+        assert_eq!(minimizer.failure.location, failure.location);
+
+        let mut minimizer = Minimizer::new(
+            TwoBugs(0),
+            steps,
+            failure,
+            FailureIdentity::Any,
+            Executor::default(),
+        );
+        minimizer.minimize();
+        assert_eq!(minimizer.steps, vec![0]);
+        assert_eq!(minimizer.failure.message, "bug b");
+        assert_eq!(minimizer.original.message, "bug a");
+    }
+
+    #[derive(Clone, Debug)]
+    struct TwoErrors(usize);
+    impl Arbitrary for TwoErrors {
+        fn gen(_: &mut Gen) -> Self {
+            Self(0)
+        }
+    }
+    impl Shrink for TwoErrors {}
+    impl ModelState for TwoErrors {
+        type Step = u32;
+        type Error = String;
+        fn step(&mut self, step: u32) -> Result<(), String> {
+            self.0 += 1;
+            match (self.0, step) {
+                (1, 0) => Err("bug b".to_owned()),
+                (3.., 0) => Err("bug a".to_owned()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn no_error_slippage() {
+        let steps = vec![5, 5, 0];
+        let failure = ModelChecker::run_steps(TwoErrors(0), &steps, None, |_| ())
+            .unwrap_err()
+            .0;
+        let mut minimizer = Minimizer::new(
+            TwoErrors(0),
+            steps,
+            failure,
+            FailureIdentity::default(),
+            Executor::default(),
+        );
+        minimizer.minimize();
+        assert_eq!(minimizer.steps, vec![1, 0, 0]);
+        assert_eq!(
+            minimizer.failure.kind,
+            FailureKind::Error("bug a".to_owned())
+        );
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Even(u32);
+    impl Arbitrary for Even {
+        fn gen(g: &mut Gen) -> Self {
+            Self(2 * g.gen_range(1..1000))
+        }
+    }
+    impl Shrink for Even {}
+    #[derive(Clone, Debug)]
+    struct EvenModel;
+    impl Arbitrary for EvenModel {
+        fn gen(_: &mut Gen) -> Self {
+            Self
+        }
+    }
+    impl Shrink for EvenModel {}
+    impl ModelState for EvenModel {
+        type Step = Even;
+        type Error = Infallible;
+        fn step(&mut self, step: Even) -> Result<(), Infallible> {
+            assert!(step.0.is_multiple_of(2) && step.0 < 100);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn shrink_choices() {
+        let mut random = ModelChecker::<EvenModel>::with_seed(11);
+        let mut choices = ModelChecker::<EvenModel>::with_seed(11).generation(Generation::Choices);
+        let fail = choices.run(8).unwrap_err();
+        assert_eq!(fail.steps, vec![Even(100)]);
+        // both modes find the same failure before shrinking
+        let unshrunk = random.run(8).unwrap_err();
+        assert_eq!((unshrunk.seed, unshrunk.run), (fail.seed, fail.run));
+        assert_eq!(unshrunk.steps.len(), 1);
+    }
+
+    #[derive(Clone, Debug)]
+    struct GenPanic;
+    impl Arbitrary for GenPanic {
+        fn gen(_: &mut Gen) -> Self {
+            Self
+        }
+    }
+    impl Shrink for GenPanic {}
+    impl ModelState for GenPanic {
+        type Step = u8;
+        type Error = Infallible;
+        fn step(&mut self, step: u8) -> Result<(), Infallible> {
+            assert!(step <= 200, "step bug");
+            Ok(())
+        }
+        fn gen_step(&self, g: &mut Gen) -> u8 {
+            let step = g.gen();
+            assert!(step != 0, "generator bug");
+            step
+        }
+    }
+
+    #[test]
+    fn gen_step_panic() {
+        // the generator panics before any step fails in the first run of this seed
+        let mut checker = ModelChecker::<GenPanic>::with_seed(65);
+        let panic =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| checker.run(32).is_ok()));
+        assert_eq!(panic.unwrap_err().downcast_ref(), Some(&"generator bug"));
+
+        // candidates whose generator panics are rejected while shrinking
+        let mut checker = ModelChecker::<GenPanic>::with_seed(0).generation(Generation::Choices);
+        let fail = checker.run(32).unwrap_err();
+        assert_eq!(fail.steps, [201]);
+        assert_eq!(checker.replay(&fail).unwrap_err(), fail.failure);
+    }
+
+    #[test]
+    fn panic_output() {
+        let mut checker = ModelChecker::<Threshold>::with_seed(3);
+        let fail = (0..100).find_map(|_| checker.run(16).err()).unwrap();
+        assert!(fail.panic_output.is_empty());
+
+        let checker = ModelChecker::<Threshold>::with_seed(3);
+        let mut checker = checker.panic_output(PanicOutput::Capture);
+        let captured = (0..100).find_map(|_| checker.run(16).err()).unwrap();
+        assert_eq!(captured.steps, fail.steps);
+        // the original failure, and the failing candidates while shrinking
+        assert!(captured.panic_output.len() > 1);
+        let thread = "thread 'test::panic_output' panicked at src/lib.rs:";
+        for message in &captured.panic_output {
+            assert!(message.starts_with(thread) && message.ends_with(":\nover threshold"));
+        }
+    }
+
+    #[test]
+    fn std_edge_cases() {
+        let mut rng = DefaultRng::seed_from_u64(0);
+        let mut g = Gen::with_size(&mut rng, 4);
+        let values: Vec<(u8, i64, Vec<char>)> = (0..1000).map(|_| Arbitrary::gen(&mut g)).collect();
+        assert!(values.iter().any(|v| v.0 == u8::MAX));
+        assert!(values.iter().any(|v| v.1 == i64::MIN));
+        assert!(values.iter().any(|v| v.2.is_empty()));
+        assert!(values.iter().any(|v| v.2.iter().any(|c| !c.is_ascii())));
+        // collections are usually bounded by the size, but sometimes much longer
+        let long = values.iter().filter(|v| v.2.len() > 4).count();
+        assert!((1..100).contains(&long));
+    }
+
+    #[derive(Clone, Debug)]
+    struct AbortGen;
+    impl Arbitrary for AbortGen {
+        fn gen(_: &mut Gen) -> Self {
+            std::process::abort()
+        }
+    }
+    impl Shrink for AbortGen {}
+    impl ModelState for AbortGen {
+        type Step = u8;
+        type Error = Infallible;
+        fn step(&mut self, _: u8) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct AbortGenStep;
+    impl Arbitrary for AbortGenStep {
+        fn gen(_: &mut Gen) -> Self {
+            Self
+        }
+    }
+    impl Shrink for AbortGenStep {}
+    impl ModelState for AbortGenStep {
+        type Step = u8;
+        type Error = Infallible;
+        fn step(&mut self, step: u8) -> Result<(), Infallible> {
+            assert!(step <= 200, "step bug");
+            Ok(())
+        }
+        fn gen_step(&self, g: &mut Gen) -> u8 {
+            let step = g.gen();
+            if step == 0 {
+                std::process::abort();
+            }
+            step
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn isolation_generation() {
+        let mut checker = ModelChecker::<AbortGen>::with_seed(0).isolation(Isolation::Fork);
+        let panic =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| checker.run(8).is_ok()));
+        let message = format!(
+            "generating the trace of seed {:#x} failed in the child process: process killed by \
+             signal 6 (SIGABRT)",
+            derive_seed(0, 0)
+        );
+        assert_eq!(panic.unwrap_err().downcast_ref(), Some(&message));
+
+        // candidates whose generator crashes are rejected while shrinking
+        let mut checker = ModelChecker::<AbortGenStep>::with_seed(0)
+            .generation(Generation::Choices)
+            .isolation(Isolation::Fork);
+        let fail = checker.run(32).unwrap_err();
+        assert_eq!(fail.steps, [201]);
+        assert_eq!(fail.failure.message, "step bug");
+    }
+
+    #[cfg(feature = "macros")]
+    mod derived {
+        use super::*;
+
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        enum Op {
+            #[arbitrary(weight = 3)]
+            Push(#[arbitrary(range = 10..20)] u32),
+            Pop,
+            #[arbitrary(weight = 0)]
+            #[allow(dead_code)]
+            Never {
+                x: u8,
+            },
+        }
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        struct Stack {
+            #[arbitrary(with = empty)]
+            items: Vec<u32>,
+        }
+        fn empty(_: &mut Gen) -> Vec<u32> {
+            Vec::new()
+        }
+        #[derive(Clone, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        struct Overflow(usize);
+        impl ModelState for Stack {
+            type Step = Op;
+            type Error = Overflow;
+            fn step(&mut self, step: Op) -> Result<(), Overflow> {
+                match step {
+                    Op::Push(x) => self.items.push(x),
+                    Op::Pop => drop(self.items.pop()),
+                    Op::Never { .. } => unreachable!(),
+                }
+                if self.items.len() >= 3 {
+                    return Err(Overflow(self.items.len()));
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn derive() {
+            let mut rng = DefaultRng::seed_from_u64(0);
+            let ops: Vec<Op> = (0..1000)
+                .map(|_| Op::gen(&mut Gen::new(&mut rng)))
+                .collect();
+            let pushes = ops.iter().filter(|op| matches!(op, Op::Push(_))).count();
+            assert!((650..850).contains(&pushes));
+            assert!(!ops.iter().any(|op| matches!(op, Op::Never { .. })));
+
+            let fail = ModelChecker::<Stack>::with_seed(1).run(32).unwrap_err();
+            assert_eq!(fail.steps, vec![Op::Push(10); 3]);
+            assert_eq!(fail.failure.kind, FailureKind::Error(Overflow(3)));
+        }
+
+        #[test]
+        fn history() {
+            let fail = ModelChecker::<Stack>::with_seed(1).run(32).unwrap_err();
+            assert_eq!(fail.history.len(), fail.steps.len() + 1);
+            assert_eq!(fail.history[0], format!("{:#?}", fail.state));
+            let report = fail.to_string();
+            let step = "  2: Push(10)\n      Stack {\n          items: [\n    +         10,\n";
+            assert!(report.contains(step));
+        }
+
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug)]
+        struct Code;
+        impl ModelState for Code {
+            type Step = u8;
+            type Error = Infallible;
+            fn step(&mut self, step: u8) -> Result<(), Infallible> {
+                if step > 200 {
+                    std::panic::panic_any(step);
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn panic_payload() {
+            let mut checker = ModelChecker::<Code>::with_seed(0);
+            let fail = checker.run(32).unwrap_err();
+            assert_eq!(fail.failure.message, "UNABLE TO SHOW RESULT OF PANIC.");
+
+            let format: PayloadFormatter = |payload| {
+                let code = payload.downcast_ref::<u8>()?;
+                Some(format!("code {code}"))
+            };
+            let mut checker = ModelChecker::<Code>::with_seed(0)
+                .panic_formatter(format)
+                .backtraces(true);
+            let fail = checker.run(32).unwrap_err();
+            assert_eq!(fail.failure.message, "code 201");
+            assert_eq!(fail.failure.location.unwrap().file, file!());
+            let backtrace = fail.backtrace.unwrap();
+            assert!(backtrace.contains("as modelcheck::ModelState>::step"));
+        }
+
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Recover;
+        impl ModelState for Recover {
+            type Step = u8;
+            type Error = Infallible;
+            fn step(&mut self, step: u8) -> Result<(), Infallible> {
+                if step > 200 {
+                    let _ = std::panic::catch_unwind(|| panic!("recovered"));
+                    panic!("{}", line!());
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn panic_location() {
+            let mut checker =
+                ModelChecker::<Recover>::with_seed(0).panic_output(PanicOutput::Silent);
+            let fail = checker.run(32).unwrap_err();
+            let location = fail.failure.location.unwrap();
+            assert_eq!(location.line.to_string(), fail.failure.message);
+        }
+
+        #[test]
+        fn replay() {
+            let mut checker = ModelChecker::<Stack>::with_seed(1);
+            let mut fail = checker.run(32).unwrap_err();
+            assert_eq!(checker.replay(&fail), Err(fail.failure.clone()));
+            fail.steps.insert(2, Op::Pop);
+            assert_eq!(checker.replay(&fail), Ok(()));
+        }
+
+        #[test]
+        fn regression_test() {
+            let fail = ModelChecker::<Stack>::with_seed(1).run(32).unwrap_err();
+            let expected = r#"#[test]
+fn stack_regression() {
+    use modelcheck::ModelState as _;
+    // seed 0x910a2dec89025cc1
+    let mut state = Stack { items: vec![] };
+    state.invariant().unwrap();
+    state.step(Op::Push(10u32)).unwrap();
+    state.invariant().unwrap();
+    state.step(Op::Push(10u32)).unwrap();
+    state.invariant().unwrap();
+    let error = state.step(Op::Push(10u32)).unwrap_err();
+    assert_eq!(format!("{error:?}"), "Overflow(3)");
+}
+"#;
+            assert_eq!(fail.regression_test("stack_regression"), expected);
+
+            let fail = ModelChecker::<Files>::with_seed(5).run(32).unwrap_err();
+            let expected = r#"#[test]
+#[should_panic(expected = "assertion `left != right` failed: closed handle 2\n  left: 2\n right: 2")]
+fn files_regression() {
+    use modelcheck::ModelState as _;
+    // seed 0x63033b0ca389c35a
+    let mut state = Files { open: vec![], next: 0u32 };
+    state.invariant().unwrap();
+    state.step(FileStep::Open).unwrap();
+    state.invariant().unwrap();
+    state.step(FileStep::Open).unwrap();
+    state.invariant().unwrap();
+    state.step(FileStep::Open).unwrap();
+    state.invariant().unwrap();
+    state.step(FileStep::Close(2u32)).unwrap();
+    state.invariant().unwrap();
+}
+"#;
+            assert_eq!(fail.regression_test("files_regression"), expected);
+
+            // the message of a panic without a string payload cannot be expected
+            let fail = ModelChecker::<Code>::with_seed(0).run(32).unwrap_err();
+            let test = fail.regression_test("code_regression");
+            assert!(test.starts_with("#[test]\n#[should_panic]\nfn code_regression() {"));
+            let format: PayloadFormatter = |payload| Some(format!("{payload:?}"));
+            let mut checker = ModelChecker::<Code>::with_seed(0).panic_formatter(format);
+            let test = checker
+                .run(32)
+                .unwrap_err()
+                .regression_test("code_regression");
+            assert!(test.starts_with("#[test]\n#[should_panic]\nfn code_regression() {"));
+        }
+
+        // emitted by `regression_test` above, pasted by hand and formatted, so it must be updated
+        // along with the expected output there
+        #[test]
+        #[should_panic(
+            expected = "assertion `left != right` failed: closed handle 2\n  left: 2\n right: 2"
+        )]
+        fn files_regression() {
+            use modelcheck::ModelState as _;
+            // seed 0x63033b0ca389c35a
+            let mut state = Files {
+                open: vec![],
+                next: 0u32,
+            };
+            state.invariant().unwrap();
+            state.step(FileStep::Open).unwrap();
+            state.invariant().unwrap();
+            state.step(FileStep::Open).unwrap();
+            state.invariant().unwrap();
+            state.step(FileStep::Open).unwrap();
+            state.invariant().unwrap();
+            state.step(FileStep::Close(2u32)).unwrap();
+            state.invariant().unwrap();
+        }
+
+        #[cfg(feature = "serde")]
+        #[test]
+        fn save_and_load() {
+            let fail = ModelChecker::<Stack>::with_seed(1).run(32).unwrap_err();
+            let path = std::env::temp_dir().join(format!("modelcheck-{}.json", std::process::id()));
+            fail.save(&path).unwrap();
+            let loaded = FailedState::<Stack>::load(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!((loaded.seed, &loaded.steps), (fail.seed, &fail.steps));
+            assert_eq!(loaded.failure, fail.failure);
+            let checker = ModelChecker::<Stack>::with_seed(2);
+            assert_eq!(checker.replay(&loaded), Err(fail.failure));
+        }
+
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug, PartialEq)]
+        enum FileStep {
+            Open,
+            Close(#[arbitrary(range = 0..8)] u32),
+        }
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug)]
+        struct Files {
+            #[arbitrary(with = empty)]
+            open: Vec<u32>,
+            #[arbitrary(range = 0..1)]
+            next: u32,
+        }
+        impl ModelState for Files {
+            type Step = FileStep;
+            type Error = Infallible;
+            fn step(&mut self, step: FileStep) -> Result<(), Infallible> {
+                match step {
+                    FileStep::Open => {
+                        self.open.push(self.next);
+                        self.next += 1;
+                    }
+                    FileStep::Close(handle) => {
+                        assert_ne!(handle, 2, "closed handle 2");
+                        self.open.retain(|&h| h != handle);
+                    }
+                }
+                Ok(())
+            }
+            fn gen_step(&self, g: &mut Gen) -> FileStep {
+                if g.gen_bool(0.5) {
+                    return FileStep::Open;
+                }
+                g.choose(&self.open)
+                    .map_or(FileStep::Open, |&h| FileStep::Close(h))
+            }
+            fn precondition(&self, step: &FileStep) -> bool {
+                match step {
+                    FileStep::Open => true,
+                    FileStep::Close(handle) => self.open.contains(handle),
+                }
+            }
+        }
+
+        // three handles must be opened before handle 2 can be closed
+        #[modelcheck(runs = 10, max_steps = 3)]
+        fn modelcheck_attribute() -> ModelChecker<Files> {
+            ModelChecker::with_seed(5)
+        }
+
+        #[modelcheck(max_steps = 8, time_budget = Duration::from_secs(60))]
+        #[should_panic(expected = "reproduce with: `checker.run_seed(")]
+        fn modelcheck_attribute_failure() -> ModelChecker<Files> {
+            ModelChecker::with_seed(5)
+        }
+
+        #[test]
+        fn failure_store() {
+            let dir = std::env::temp_dir().join(format!("modelcheck-store-{}", std::process::id()));
+            let store = FailureStore::new(&dir);
+            let config = Config {
+                max_steps: 32,
+                ..Config::default()
+            };
+            let mut checker = ModelChecker::<Files>::with_seed(5).failure_store(store.clone());
+            let report = checker.check(&config);
+            let fail = report.failure().unwrap();
+            let case = StoredCase {
+                seed: fail.seed,
+                max_steps: fail.max_steps,
+            };
+            assert_eq!(store.load::<Files>().unwrap(), [case]);
+            assert!(store
+                .path::<Files>()
+                .ends_with("modelcheck.test.derived.Files.txt"));
+
+            // new runs are too short to fail, but the stored run still does
+            let mut checker = ModelChecker::<Files>::with_seed(6).failure_store(store.clone());
+            let report = checker.check(&Config {
+                runs: 10,
+                max_steps: 3,
+                stop_on_first_failure: false,
+                ..Config::default()
+            });
+            assert_eq!((report.replayed, report.runs), (1, 10));
+            assert_eq!(report.regressions[0].steps, fail.steps);
+            assert!(report.failures.is_empty());
+            store.save(&report.regressions[0]).unwrap();
+            assert_eq!(store.load::<Files>().unwrap().len(), 1);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn preconditions() {
+            use FileStep::*;
+            for generation in [Generation::Random, Generation::Choices] {
+                let mut checker = ModelChecker::<Files>::with_seed(4).generation(generation);
+                let fail = (0..100).find_map(|_| checker.run(32).err()).unwrap();
+                assert_eq!(fail.steps, vec![Open, Open, Open, Close(2)]);
+            }
+        }
+
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Counter(#[arbitrary(range = 0..1)] u32);
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Add(#[arbitrary(range = 0..10)] u32);
+        impl Reference for Counter {
+            type Step = Add;
+            type Output = u32;
+            fn apply(&mut self, step: &Add) -> u32 {
+                self.0 += step.0;
+                self.0
+            }
+        }
+        #[derive(Clone, Debug)]
+        struct SaturatingCounter(u32);
+        impl SystemUnderTest for SaturatingCounter {
+            type Reference = Counter;
+            fn new(reference: &Counter) -> Self {
+                Self(reference.0)
+            }
+            fn apply(&mut self, step: &Add) -> u32 {
+                self.0 = (self.0 + step.0).min(20);
+                self.0
+            }
+        }
+
+        #[test]
+        fn differential() {
+            let mut checker = ModelChecker::<Differential<SaturatingCounter>>::with_seed(0);
+            let fail = checker.run(32).unwrap_err();
+            let total: u32 = fail.steps.iter().map(|step| step.0).sum();
+            let expected = FailureKind::Error(Mismatch {
+                step: format!("{:?}", fail.failing_step().unwrap()),
+                expected: total.to_string(),
+                actual: "20".to_owned(),
+            });
+            assert_eq!(fail.failure.kind, expected);
+            assert!(fail.failing_step().unwrap().0 > 0);
+        }
+
+        #[derive(Arbitrary, Shrink, ToRust, Clone, Debug)]
+        struct Bounded {
+            #[arbitrary(range = 0..4)]
+            value: u8,
+            #[arbitrary(range = 8..16)]
+            limit: u8,
+        }
+        impl ModelState for Bounded {
+            type Step = bool;
+            type Error = Infallible;
+            fn step(&mut self, increment: bool) -> Result<(), Infallible> {
+                if increment {
+                    self.value += 1;
+                }
+                Ok(())
+            }
+            fn invariant(&self) -> Result<(), String> {
+                if self.value > self.limit {
+                    return Err("value exceeds limit".to_owned());
+                }
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn invariant() {
+            let mut checker = ModelChecker::<Bounded>::with_seed(0);
+            let fail = (0..100).find_map(|_| checker.run(64).err()).unwrap();
+            assert_eq!(fail.state.value, 0);
+            assert_eq!(fail.state.limit, 8);
+            assert_eq!(fail.steps, vec![true; 9]);
+            let kind = FailureKind::Invariant("value exceeds limit".to_owned());
+            assert_eq!(fail.failure.kind, kind);
+            assert_eq!(fail.failure.location, None);
+            // the emitted test checks the invariant on every state but the failing one, which it
+            // expects to fail
+            let test = fail.regression_test("bounded_regression");
+            assert_eq!(test.matches("state.invariant().unwrap();").count(), 9);
+            let last = "    state.step(true).unwrap();\n    \
+                assert_eq!(state.invariant(), Err(String::from(\"value exceeds limit\")));\n}\n";
+            assert!(test.ends_with(last));
+
+            let state = Bounded { value: 9, limit: 8 };
+            let failure = ModelChecker::run_steps(state, &[], None, |_| ()).unwrap_err();
+            assert_eq!((failure.0.kind, failure.1), (kind, 0));
+        }
+
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Abort;
+        impl ModelState for Abort {
+            type Step = u8;
+            type Error = Infallible;
+            fn step(&mut self, step: u8) -> Result<(), Infallible> {
+                if step > 200 {
+                    std::process::abort();
+                }
+                Ok(())
+            }
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn isolation() {
+            let crash = FailureKind::Crash(Crash::Signal(6));
+            for generation in [Generation::Random, Generation::Choices] {
+                let mut checker = ModelChecker::<Abort>::with_seed(0)
+                    .generation(generation)
+                    .isolation(Isolation::Fork);
+                let fail = checker.run(32).unwrap_err();
+                assert_eq!(fail.failure.kind, crash);
+                assert_eq!(fail.failure.message, "process killed by signal 6 (SIGABRT)");
+                assert!(matches!(fail.steps[..], [step] if step > 200));
+                assert_eq!(fail.history.len(), 1);
+                assert_eq!(checker.replay(&fail).unwrap_err().kind, crash);
+            }
+        }
+
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Hang;
+        impl ModelState for Hang {
+            type Step = u8;
+            type Error = Infallible;
+            fn step(&mut self, step: u8) -> Result<(), Infallible> {
+                if step > 200 {
+                    loop {
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn timeout() {
+            let timeout = Duration::from_millis(100);
+            let mut checker = ModelChecker::<Hang>::with_seed(0)
+                .isolation(Isolation::Fork)
+                .step_timeout(timeout);
+            let fail = checker.run(32).unwrap_err();
+            assert_eq!(
+                fail.failure.kind,
+                FailureKind::Timeout(Timeout::Step(timeout))
+            );
+            assert_eq!(fail.failure.message, "step timed out after 100ms");
+            assert_eq!(fail.steps, [201]);
+
+            let mut checker = ModelChecker::<Hang>::with_seed(0)
+                .isolation(Isolation::Fork)
+                .run_timeout(timeout);
+            let fail = checker.run(32).unwrap_err();
+            assert_eq!(
+                fail.failure.kind,
+                FailureKind::Timeout(Timeout::Run(timeout))
+            );
+            assert_eq!(fail.steps, [201]);
+        }
+
+        #[test]
+        #[should_panic(expected = "timeouts are only supported with `Isolation::Fork`")]
+        fn timeout_without_fork() {
+            let timeout = Duration::from_millis(100);
+            let mut checker = ModelChecker::<Hang>::with_seed(0).step_timeout(timeout);
+            let _ = checker.run(32);
+        }
+
+        #[derive(Arbitrary, Shrink, Clone, Debug)]
+        struct Orphan;
+        impl ModelState for Orphan {
+            type Step = u8;
+            type Error = Infallible;
+            fn step(&mut self, step: u8) -> Result<(), Infallible> {
+                if step > 200 {
+                    // the process inherits the write end of the pipe to the parent, as one forked
+                    // by another thread would, and keeps it open after this process aborts
+                    let _ = std::process::Command::new("sleep").arg("1").spawn();
+                    std::process::abort();
+                }
+                Ok(())
+            }
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn inherited_pipe() {
+            let mut checker = ModelChecker::<Orphan>::with_seed(0)
+                .isolation(Isolation::Fork)
+                .step_timeout(Duration::from_millis(500));
+            let fail = checker.run(32).unwrap_err();
+            assert_eq!(fail.failure.kind, FailureKind::Crash(Crash::Signal(6)));
+            assert_eq!(fail.steps, [201]);
+        }
+    }
 }
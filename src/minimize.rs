@@ -0,0 +1,205 @@
+use crate::{isolation::Executor, Failure, FailureIdentity, ModelChecker, ModelState, Shrink as _};
+
+/// Number of steps between the states cached by `Minimizer`.
+const CHECKPOINT_INTERVAL: usize = 16;
+
+/// Shrinks a failing trace to a locally minimal one.
+pub(crate) struct Minimizer<M: ModelState> {
+    pub state: M,
+    pub steps: Vec<M::Step>,
+    pub failure: Failure<M::Error>,
+    /// The failure of the trace before shrinking.
+    pub original: Failure<M::Error>,
+    identity: FailureIdentity<M::Error>,
+    executor: Executor,
+    /// The states after executing the first `CHECKPOINT_INTERVAL`, `2 * CHECKPOINT_INTERVAL`,
+    /// ... steps of the trace, before the failing step. A candidate trace sharing a prefix with
+    /// the trace resumes from the last of these states within the prefix.
+    checkpoints: Vec<M>,
+    /// Number of candidate traces executed so far.
+    pub replays: usize,
+}
+
+/// A candidate trace that fails in the same way as the original trace.
+struct Failing<M: ModelState> {
+    failure: Failure<M::Error>,
+    /// Number of steps executed, including the failing one.
+    executed: usize,
+    /// The candidate's checkpoints, as for `Minimizer::checkpoints`.
+    checkpoints: Vec<M>,
+}
+
+impl<M: ModelState> Minimizer<M> {
+    pub fn new(
+        state: M,
+        steps: Vec<M::Step>,
+        failure: Failure<M::Error>,
+        identity: FailureIdentity<M::Error>,
+        executor: Executor,
+    ) -> Self {
+        Self {
+            state,
+            steps,
+            original: failure.clone(),
+            failure,
+            identity,
+            executor,
+            checkpoints: Vec::new(),
+            replays: 0,
+        }
+    }
+
+    /// Alternate step removal and value shrinking until neither makes progress.
+    pub fn minimize(&mut self) {
+        loop {
+            let removed = self.remove_steps();
+            let shrunk = self.shrink_values();
+            if !removed && !shrunk {
+                break;
+            }
+        }
+    }
+
+    /// Execute a candidate trace from `state`, or from the current initial state if `None`, and
+    /// return it if it fails in the same way as the original trace. The first `unchanged` steps
+    /// of a candidate from the current initial state must be those of the current trace, which
+    /// lets it resume from a checkpoint.
+    fn test(
+        &mut self,
+        state: Option<&M>,
+        steps: &[M::Step],
+        unchanged: usize,
+    ) -> Option<Failing<M>> {
+        self.replays += 1;
+        let resume = match state {
+            Some(_) => 0,
+            None => (unchanged / CHECKPOINT_INTERVAL).min(self.checkpoints.len()),
+        };
+        let skip = resume * CHECKPOINT_INTERVAL;
+        let start = match resume {
+            0 => state.unwrap_or(&self.state),
+            n => &self.checkpoints[n - 1],
+        };
+        let mut recorded = Vec::new();
+        let run = |terminated| {
+            recorded.clear();
+            let mut position = skip;
+            let record = |state: &M| {
+                position += 1;
+                if position.is_multiple_of(CHECKPOINT_INTERVAL) {
+                    recorded.push(state.clone());
+                }
+            };
+            ModelChecker::<M>::run_steps(start.clone(), &steps[skip..], terminated, record)
+        };
+        let (failure, executed) = (self.executor.execute(run, Result::is_err)?.err())
+            .filter(|(failure, _)| self.identity.matches::<M>(&self.original, failure))?;
+        let executed = skip + executed;
+        let mut checkpoints = self.checkpoints[..resume].to_vec();
+        checkpoints.extend(recorded);
+        // resuming after the failing step would skip the failure
+        checkpoints.truncate(executed.saturating_sub(1) / CHECKPOINT_INTERVAL);
+        Some(Failing {
+            failure,
+            executed,
+            checkpoints,
+        })
+    }
+
+    /// Make `failing` the current failure, once its trace is the current trace.
+    fn accept(&mut self, failing: Failing<M>) {
+        self.failure = failing.failure;
+        self.checkpoints = failing.checkpoints;
+    }
+
+    /// Remove steps with `ddmin`. Returns true if any step was removed.
+    fn remove_steps(&mut self) -> bool {
+        let mut steps = std::mem::take(&mut self.steps);
+        let removed = ddmin(&mut steps, |candidate, unchanged| {
+            let failing = self.test(None, candidate, unchanged)?;
+            let executed = failing.executed;
+            self.accept(failing);
+            Some(executed)
+        });
+        self.steps = steps;
+        removed
+    }
+
+    /// Replace each step, and then the initial state, with the first of its `Shrink` candidates
+    /// that still fails, until none do. Returns true if any value was replaced.
+    fn shrink_values(&mut self) -> bool {
+        let mut shrunk = false;
+        for index in 0..self.steps.len() {
+            'step: loop {
+                for candidate in self.steps[index].shrink() {
+                    let mut steps = self.steps.clone();
+                    steps[index] = candidate;
+                    if let Some(failing) = self.test(None, &steps, index) {
+                        self.steps = steps;
+                        self.accept(failing);
+                        shrunk = true;
+                        continue 'step;
+                    }
+                }
+                break;
+            }
+        }
+        let steps = self.steps.clone();
+        'state: loop {
+            for candidate in self.state.shrink() {
+                if let Some(failing) = self.test(Some(&candidate), &steps, 0) {
+                    self.state = candidate;
+                    self.accept(failing);
+                    shrunk = true;
+                    continue 'state;
+                }
+            }
+            break;
+        }
+        shrunk
+    }
+}
+
+/// Remove items using delta debugging (ddmin): split `items` into `n` chunks and try removing
+/// each one, refining the split when no chunk can be removed. The result is 1-minimal, i.e. no
+/// single item can be removed. Returns true if any item was removed.
+///
+/// `test` is called with a candidate and the number of its leading items that are unchanged
+/// from `items`. It returns `None` if the candidate does not reproduce the failure, and otherwise
+/// the number of leading items it used. Later items are dropped.
+pub(crate) fn ddmin<T: Clone>(
+    items: &mut Vec<T>,
+    mut test: impl FnMut(&[T], usize) -> Option<usize>,
+) -> bool {
+    let mut removed = false;
+    let mut n = 2;
+    while !items.is_empty() {
+        let len = items.len();
+        n = n.min(len);
+        let chunk = len.div_ceil(n);
+        let mut found = false;
+        let mut start = 0;
+        while start < items.len() {
+            let end = (start + chunk).min(items.len());
+            let mut candidate = items.clone();
+            candidate.drain(start..end);
+            match test(&candidate, start) {
+                Some(used) => {
+                    candidate.truncate(used);
+                    *items = candidate;
+                    found = true;
+                    removed = true;
+                }
+                None => start = end,
+            }
+        }
+        if found {
+            n = n.saturating_sub(1).max(2);
+        } else if n >= len {
+            break;
+        } else {
+            n = (n * 2).min(len);
+        }
+    }
+    removed
+}
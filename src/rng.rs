@@ -0,0 +1,322 @@
+use rand::{Error, RngCore, SeedableRng};
+use std::ops::{Range, RangeInclusive};
+
+/// The random number generator used by `ModelChecker`.
+///
+/// This is xoshiro256++, seeded from a `u64` by SplitMix64. Unlike `rand::rngs::SmallRng`, the
+/// algorithm is fixed by this crate and does not depend on the target's pointer width or on the
+/// version of `rand`, so a seed reported by a failure reproduces the same sequence everywhere.
+/// The same holds for values sampled with `Gen`'s own methods, but values produced through
+/// `rand`'s distributions (e.g. `rand::Rng::gen_range`) are subject to `rand`'s own
+/// value-stability policy.
+#[derive(Clone, Debug)]
+pub struct DefaultRng {
+    s: [u64; 4],
+}
+
+impl RngCore for DefaultRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let result = (self.s[0].wrapping_add(self.s[3]))
+            .rotate_left(23)
+            .wrapping_add(self.s[0]);
+        let t = self.s[1] << 17;
+        self.s[2] ^= self.s[0];
+        self.s[3] ^= self.s[1];
+        self.s[1] ^= self.s[2];
+        self.s[0] ^= self.s[3];
+        self.s[2] ^= t;
+        self.s[3] = self.s[3].rotate_left(45);
+        result
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for DefaultRng {
+    type Seed = [u8; 32];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        // the all-zero state is a fixed point of xoshiro
+        if seed == [0; 32] {
+            return Self::seed_from_u64(0);
+        }
+        let mut s = [0; 4];
+        for (s, chunk) in s.iter_mut().zip(seed.chunks_exact(8)) {
+            *s = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        Self { s }
+    }
+
+    fn seed_from_u64(mut state: u64) -> Self {
+        let mut s = [0; 4];
+        for s in &mut s {
+            *s = splitmix64(&mut state);
+        }
+        Self { s }
+    }
+}
+
+/// Advance a SplitMix64 stream and return its next output.
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Source of randomness passed to `Arbitrary::gen`.
+///
+/// `Gen` wraps any `RngCore`, and implements `RngCore` itself, so generators can use the methods
+/// of `rand::Rng` directly. Its own `gen_range`, `gen_ratio`, `gen_bool` and `choose` take
+/// precedence over those of `rand`, and are implemented by this crate so that a seed generates
+/// the same values with any version of `rand` and on targets of any pointer width. Its size
+/// bounds the length of typical generated collections.
+pub struct Gen<'a> {
+    rng: &'a mut dyn RngCore,
+    size: usize,
+}
+
+impl<'a> Gen<'a> {
+    /// Size of a `Gen` created by `Gen::new`.
+    pub const DEFAULT_SIZE: usize = 32;
+
+    pub fn new(rng: &'a mut dyn RngCore) -> Self {
+        Self::with_size(rng, Self::DEFAULT_SIZE)
+    }
+
+    pub fn with_size(rng: &'a mut dyn RngCore, size: usize) -> Self {
+        Self { rng, size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Length for a generated collection. This is usually at most `size`, but is biased toward
+    /// empty collections and occasionally up to 8 times `size`.
+    pub fn gen_len(&mut self) -> usize {
+        match self.gen_range(0..16) {
+            0 | 1 => 0,
+            2 => self.gen_range(0..=self.size * 8),
+            _ => self.gen_range(0..=self.size),
+        }
+    }
+
+    /// A value drawn uniformly from `range`, a `Range` or `RangeInclusive` of integers, floats or
+    /// chars. Panics if the range is empty.
+    pub fn gen_range<T: Uniform, R: GenRange<T>>(&mut self, range: R) -> T {
+        range.sample(self)
+    }
+
+    /// Return true with probability `numerator / denominator`.
+    pub fn gen_ratio(&mut self, numerator: u32, denominator: u32) -> bool {
+        assert!(
+            numerator <= denominator && denominator > 0,
+            "invalid ratio {numerator}/{denominator}"
+        );
+        self.gen_range(0..denominator) < numerator
+    }
+
+    /// Return true with probability `p`.
+    pub fn gen_bool(&mut self, p: f64) -> bool {
+        assert!((0.0..=1.0).contains(&p), "invalid probability {p}");
+        self.gen_range(0.0..1.0) < p
+    }
+
+    /// A uniformly chosen element of `items`, or `None` if it is empty.
+    pub fn choose<'b, T>(&mut self, items: &'b [T]) -> Option<&'b T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(&items[self.gen_range(0..items.len())])
+    }
+
+    /// A value drawn uniformly from `0..=span`. Integers of every type are drawn through this, so
+    /// that a range produces the same values whatever the width of its type.
+    fn gen_offset(&mut self, span: u128) -> u128 {
+        if let Ok(span) = u64::try_from(span) {
+            let Some(n) = span.checked_add(1) else {
+                return self.next_u64().into();
+            };
+            // widening multiplication, rejecting the low products that would bias the result
+            let zone = u64::MAX - n.wrapping_neg() % n;
+            loop {
+                let product = u128::from(self.next_u64()) * u128::from(n);
+                if product as u64 <= zone {
+                    return product >> 64;
+                }
+            }
+        }
+        let mask = u128::MAX >> span.leading_zeros();
+        loop {
+            let offset = (u128::from(self.next_u64()) << 64 | u128::from(self.next_u64())) & mask;
+            if offset <= span {
+                return offset;
+            }
+        }
+    }
+}
+
+/// A range that `Gen::gen_range` can draw a `T` from: a `Range` or `RangeInclusive`.
+pub trait GenRange<T> {
+    fn sample(self, g: &mut Gen) -> T;
+}
+
+impl<T: Uniform> GenRange<T> for Range<T> {
+    fn sample(self, g: &mut Gen) -> T {
+        T::sample(g, self.start, self.end, false)
+    }
+}
+
+impl<T: Uniform> GenRange<T> for RangeInclusive<T> {
+    fn sample(self, g: &mut Gen) -> T {
+        let (start, end) = self.into_inner();
+        T::sample(g, start, end, true)
+    }
+}
+
+/// A type `Gen::gen_range` can draw uniformly from a range of.
+pub trait Uniform: Sized {
+    /// A value between `start` and `end`, which is included if `inclusive` is set.
+    fn sample(g: &mut Gen, start: Self, end: Self, inclusive: bool) -> Self;
+}
+
+// integers are mapped to `u128` keys in the same order, with the sign bit of signed integers
+// flipped
+macro_rules! uniform_int {
+    ($($t:ty: $signed:expr),*) => {$(
+        impl Uniform for $t {
+            fn sample(g: &mut Gen, start: Self, end: Self, inclusive: bool) -> Self {
+                const FLIP: u128 = if $signed { 1 << 127 } else { 0 };
+                let key = |x: $t| (x as i128 as u128) ^ FLIP;
+                let (start, end) = (key(start), key(end));
+                assert!(start < end || inclusive && start == end, "cannot sample empty range");
+                let span = end - start - u128::from(!inclusive);
+                ((start + g.gen_offset(span)) ^ FLIP) as $t
+            }
+        }
+    )*};
+}
+uniform_int!(
+    u8: false, u16: false, u32: false, u64: false, u128: false, usize: false,
+    i8: true, i16: true, i32: true, i64: true, i128: true, isize: true
+);
+
+// floats are drawn from a uniform fraction with as many bits as the mantissa
+macro_rules! uniform_float {
+    ($($t:ty: $bits:expr),*) => {$(
+        impl Uniform for $t {
+            fn sample(g: &mut Gen, start: Self, end: Self, inclusive: bool) -> Self {
+                assert!(start < end || inclusive && start == end, "cannot sample empty range");
+                assert!((end - start).is_finite(), "cannot sample non-finite range");
+                let max = (1u64 << $bits) - 1;
+                if inclusive {
+                    let fraction = g.gen_offset(max.into()) as $t / max as $t;
+                    return (start + (end - start) * fraction).min(end);
+                }
+                loop {
+                    let fraction = g.gen_offset(max.into()) as $t / (max + 1) as $t;
+                    let value = start + (end - start) * fraction;
+                    // rounding can reach the end of the range
+                    if value < end {
+                        return value;
+                    }
+                }
+            }
+        }
+    )*};
+}
+uniform_float!(f32: 24, f64: 53);
+
+// chars are drawn as code points, skipping the surrogates, which are not chars
+impl Uniform for char {
+    fn sample(g: &mut Gen, start: Self, end: Self, inclusive: bool) -> Self {
+        const SURROGATES: u32 = 0xe000 - 0xd800;
+        let key = |c: char| {
+            let c = c as u32;
+            if c >= 0xe000 {
+                c - SURROGATES
+            } else {
+                c
+            }
+        };
+        let key = u32::sample(g, key(start), key(end), inclusive);
+        let code = if key >= 0xd800 { key + SURROGATES } else { key };
+        char::from_u32(code).expect("code points outside the surrogates are chars")
+    }
+}
+
+impl RngCore for Gen<'_> {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stable_output() {
+        // reference values for xoshiro256++ seeded by SplitMix64 from 0
+        let mut rng = DefaultRng::seed_from_u64(0);
+        let values: Vec<u64> = (0..3).map(|_| rng.next_u64()).collect();
+        assert_eq!(values, STABLE_OUTPUT);
+    }
+
+    #[test]
+    fn stable_sampling() {
+        let mut rng = DefaultRng::seed_from_u64(0);
+        let mut g = Gen::new(&mut rng);
+        let ints = (
+            g.gen_range(0..10u8),
+            g.gen_range(-5..=5i64),
+            g.gen_range(0..1000usize),
+        );
+        let other = (
+            g.gen_range(0.0..1.0f64),
+            g.gen_range('a'..='z'),
+            g.gen_ratio(1, 3),
+        );
+        assert_eq!(ints, (3, -1, 359));
+        assert_eq!(other, (0.011455508934653635, 'm', true));
+        assert_eq!(g.choose(&[1, 2, 3]), Some(&3));
+        // a range draws the same values whatever the width of its type
+        let mut rng = DefaultRng::seed_from_u64(0);
+        let mut g = Gen::new(&mut rng);
+        let narrow: Vec<u8> = (0..8).map(|_| g.gen_range(0..200)).collect();
+        let mut rng = DefaultRng::seed_from_u64(0);
+        let mut g = Gen::new(&mut rng);
+        let wide: Vec<usize> = (0..8).map(|_| g.gen_range(0..200)).collect();
+        assert!(narrow.iter().zip(&wide).all(|(&n, &w)| usize::from(n) == w));
+    }
+
+    const STABLE_OUTPUT: [u64; 3] = [0x53175d61490b23df, 0x61da6f3dc380d507, 0x5c0fdf91ec9a7bfc];
+}
@@ -0,0 +1,140 @@
+use std::{env, fs, io, time::Duration};
+
+/// File read by `Settings::load` when `MODELCHECK_CONFIG` is not set, relative to the current
+/// directory. `cargo test` runs tests from the package root.
+const CONFIG_FILE: &str = "modelcheck.toml";
+
+/// Checker settings given outside of code. Each one is `None` unless set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Settings {
+    pub seed: Option<u64>,
+    pub runs: Option<u64>,
+    pub min_steps: Option<usize>,
+    pub max_steps: Option<usize>,
+    pub time_budget: Option<Duration>,
+    pub stop_on_first_failure: Option<bool>,
+    pub threads: Option<usize>,
+}
+
+impl Settings {
+    const KEYS: [&'static str; 7] = [
+        "seed",
+        "runs",
+        "min_steps",
+        "max_steps",
+        "time_budget",
+        "stop_on_first_failure",
+        "threads",
+    ];
+
+    /// Read settings from `modelcheck.toml`, or the file named by `MODELCHECK_CONFIG`, and then
+    /// from `MODELCHECK_*` environment variables, which take precedence. A missing
+    /// `modelcheck.toml` is ignored.
+    ///
+    /// # Panics
+    ///
+    /// If the file cannot be read or parsed, or if a value is invalid.
+    pub fn load() -> Self {
+        let (path, required) = match env::var("MODELCHECK_CONFIG") {
+            Ok(path) => (path, true),
+            Err(_) => (CONFIG_FILE.to_owned(), false),
+        };
+        let mut settings = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents).unwrap_or_else(|e| panic!("{path}: {e}")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Self::default(),
+            Err(e) => panic!("failed to read {path}: {e}"),
+        };
+        for key in Self::KEYS {
+            let var = format!("MODELCHECK_{}", key.to_uppercase());
+            if let Ok(value) = env::var(&var) {
+                settings
+                    .set(key, value.trim())
+                    .unwrap_or_else(|e| panic!("{var}: {e}"));
+            }
+        }
+        settings
+    }
+
+    /// Parse `key = value` lines, where values are integers, decimal numbers or booleans. This
+    /// is the subset of TOML needed for a flat table of settings.
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut settings = Self::default();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = value`", index + 1))?;
+            settings
+                .set(key.trim(), value.trim())
+                .map_err(|e| format!("line {}: {e}", index + 1))?;
+        }
+        Ok(settings)
+    }
+
+    /// Set the setting named `key`. `time_budget` is given in seconds.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.replace('_', "");
+        let invalid = || format!("invalid value for `{key}`: {value}");
+        match key {
+            "seed" => self.seed = Some(parse_u64(&value).ok_or_else(invalid)?),
+            "runs" => self.runs = Some(value.parse().map_err(|_| invalid())?),
+            "min_steps" => self.min_steps = Some(value.parse().map_err(|_| invalid())?),
+            "max_steps" => self.max_steps = Some(value.parse().map_err(|_| invalid())?),
+            "time_budget" => {
+                let seconds: f64 = value.parse().map_err(|_| invalid())?;
+                let budget = Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?;
+                self.time_budget = Some(budget);
+            }
+            "stop_on_first_failure" => {
+                self.stop_on_first_failure = Some(value.parse().map_err(|_| invalid())?)
+            }
+            "threads" => self.threads = Some(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("unknown setting `{key}`")),
+        }
+        Ok(())
+    }
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal integer, since seeds are usually printed in hex.
+fn parse_u64(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let settings = Settings::parse(
+            "# CI settings\n\
+             seed = 0xdead_beef\n\
+             runs = 10_000 # soak\n\
+             \n\
+             time_budget = 1.5\n\
+             stop_on_first_failure = false\n\
+             threads = 4\n",
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                seed: Some(0xdeadbeef),
+                runs: Some(10_000),
+                time_budget: Some(Duration::from_millis(1500)),
+                stop_on_first_failure: Some(false),
+                threads: Some(4),
+                ..Settings::default()
+            }
+        );
+        assert!(Settings::parse("runs = -1").is_err());
+        assert!(Settings::parse("seeds = 1").is_err());
+        assert!(Settings::parse("runs").is_err());
+    }
+}
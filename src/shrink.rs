@@ -0,0 +1,217 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::Hash,
+    iter,
+};
+
+/// Produce simpler variants of a value, used to minimize failing traces.
+///
+/// Candidates should be strictly simpler than `self` and are tried in order, so the most
+/// aggressive simplifications should come first. The default implementation does not shrink.
+pub trait Shrink: Clone + 'static {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new(iter::empty())
+    }
+}
+
+impl Shrink for () {}
+
+impl Shrink for bool {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new(self.then_some(false).into_iter())
+    }
+}
+
+macro_rules! shrink_unsigned {
+    ($($t:ty),*) => {$(
+        impl Shrink for $t {
+            fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+                let x = *self;
+                // x - x/2, x - x/4, ..., x - 1, starting from 0
+                let halves = iter::successors(Some(x / 2), |d| Some(d / 2))
+                    .take_while(|&d| d > 0)
+                    .map(move |d| x - d);
+                Box::new((x != 0).then_some(0).into_iter().chain(halves.filter(|&y| y != 0)))
+            }
+        }
+    )*};
+}
+shrink_unsigned!(u8, u16, u32, u64, u128, usize);
+
+macro_rules! shrink_signed {
+    ($($t:ty),*) => {$(
+        impl Shrink for $t {
+            fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+                let x = *self;
+                let halves = iter::successors(Some(x / 2), |d| Some(d / 2))
+                    .take_while(|&d| d != 0)
+                    .map(move |d| x - d)
+                    .filter(|&y| y != 0);
+                // prefer the positive counterpart of a negative value
+                let negated = (x < 0).then(|| x.checked_neg()).flatten();
+                Box::new((x != 0).then_some(0).into_iter().chain(negated).chain(halves))
+            }
+        }
+    )*};
+}
+shrink_signed!(i8, i16, i32, i64, i128, isize);
+
+macro_rules! shrink_float {
+    ($($t:ty),*) => {$(
+        impl Shrink for $t {
+            fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+                let x = *self;
+                if x == 0.0 {
+                    return Box::new(iter::empty());
+                }
+                let mut candidates = vec![0.0];
+                if !x.is_finite() {
+                    candidates.push(<$t>::MAX.copysign(x));
+                } else {
+                    if x < 0.0 {
+                        candidates.push(-x);
+                    }
+                    candidates.push(x.trunc());
+                    candidates.push((x / 2.0).trunc());
+                }
+                candidates.dedup();
+                Box::new(candidates.into_iter().filter(move |&y| y != x))
+            }
+        }
+    )*};
+}
+shrink_float!(f32, f64);
+
+impl Shrink for char {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let c = *self;
+        let candidates = ['a', 'b', 'c', 'A', ' ', '0'];
+        let simpler = candidates.into_iter().take_while(move |&s| s != c);
+        // then shrink toward 'a' by code point
+        let lower = (c as u32)
+            .shrink()
+            .filter(|&x| x >= 'a' as u32)
+            .filter_map(char::from_u32);
+        Box::new(simpler.chain(lower).filter(move |&s| s != c))
+    }
+}
+
+impl Shrink for String {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let chars: Vec<char> = self.chars().collect();
+        Box::new(chars.shrink().map(|chars| chars.into_iter().collect()))
+    }
+}
+
+impl<T: Shrink> Shrink for Box<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new((**self).shrink().map(Box::new))
+    }
+}
+
+impl<T: Shrink> Shrink for Option<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            None => Box::new(iter::empty()),
+            Some(x) => Box::new(iter::once(None).chain(x.shrink().map(Some))),
+        }
+    }
+}
+
+impl<T: Shrink, E: Shrink> Shrink for Result<T, E> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        match self {
+            Ok(x) => Box::new(x.shrink().map(Ok)),
+            Err(e) => Box::new(e.shrink().map(Err)),
+        }
+    }
+}
+
+impl<T: Shrink> Shrink for Vec<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v = self.clone();
+        let len = v.len();
+        // remove chunks of decreasing size: len, len/2, ..., 1
+        let removals = iter::successors(Some(len), |&n| Some(n / 2))
+            .take_while(|&n| n > 0)
+            .flat_map(move |n| (0..=(len - n)).step_by(n).map(move |start| (start, n)))
+            .map({
+                let v = v.clone();
+                move |(start, n)| {
+                    let mut shrunk = v.clone();
+                    shrunk.drain(start..start + n);
+                    shrunk
+                }
+            });
+        // then simplify each element in place
+        let elements = (0..len).flat_map(move |i| {
+            let v = v.clone();
+            v[i].shrink().map(move |x| {
+                let mut shrunk = v.clone();
+                shrunk[i] = x;
+                shrunk
+            })
+        });
+        Box::new(removals.chain(elements))
+    }
+}
+
+impl<T: Shrink> Shrink for VecDeque<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v: Vec<T> = self.iter().cloned().collect();
+        Box::new(v.shrink().map(VecDeque::from))
+    }
+}
+
+impl<T: Shrink + Ord> Shrink for BTreeSet<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v: Vec<T> = self.iter().cloned().collect();
+        Box::new(v.shrink().map(|v| v.into_iter().collect()))
+    }
+}
+
+impl<K: Shrink + Ord, V: Shrink> Shrink for BTreeMap<K, V> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v: Vec<(K, V)> = self.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        Box::new(v.shrink().map(|v| v.into_iter().collect()))
+    }
+}
+
+impl<T: Shrink + Eq + Hash> Shrink for HashSet<T> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v: Vec<T> = self.iter().cloned().collect();
+        Box::new(v.shrink().map(|v| v.into_iter().collect()))
+    }
+}
+
+impl<K: Shrink + Eq + Hash, V: Shrink> Shrink for HashMap<K, V> {
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let v: Vec<(K, V)> = self.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        Box::new(v.shrink().map(|v| v.into_iter().collect()))
+    }
+}
+
+macro_rules! shrink_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Shrink),+> Shrink for ($($name,)+) {
+            fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+                let candidates = iter::empty::<Self>();
+                $(
+                    let t = self.clone();
+                    let candidates = candidates.chain(self.$index.shrink().map(move |x| {
+                        let mut shrunk = t.clone();
+                        shrunk.$index = x;
+                        shrunk
+                    }));
+                )+
+                Box::new(candidates)
+            }
+        }
+    };
+}
+shrink_tuple!(A 0);
+shrink_tuple!(A 0, B 1);
+shrink_tuple!(A 0, B 1, C 2);
+shrink_tuple!(A 0, B 1, C 2, D 3);
+shrink_tuple!(A 0, B 1, C 2, D 3, E 4);
+shrink_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
@@ -0,0 +1,95 @@
+use crate::{FailedState, ModelState};
+use std::{
+    any, fs,
+    io::{self, Write as _},
+    path::PathBuf,
+};
+
+/// A directory of failing runs, with a file for each model type. A checker with a store saves
+/// every failing run to it, and `ModelChecker::check` replays the stored runs of its model before
+/// generating new ones, so a fixed bug that regresses is caught without relying on the seed.
+///
+/// Runs are stored as their seed and `max_steps`, which regenerate the unshrunk trace as long as
+/// the model's generators are unchanged. Store files are meant to be checked into version
+/// control.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailureStore {
+    dir: PathBuf,
+}
+
+/// A run saved in a `FailureStore`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoredCase {
+    pub seed: u64,
+    pub max_steps: usize,
+}
+
+impl FailureStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The file holding the runs of model `M`, named after its type.
+    pub fn path<M: ModelState>(&self) -> PathBuf {
+        let name: String = (any::type_name::<M>().replace("::", "."))
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+                _ => '_',
+            })
+            .collect();
+        self.dir.join(format!("{name}.txt"))
+    }
+
+    /// The stored runs of model `M`, in the order they were saved.
+    pub fn load<M: ModelState>(&self) -> io::Result<Vec<StoredCase>> {
+        let path = self.path::<M>();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                parse_case(line).ok_or_else(|| {
+                    let message = format!("{}: invalid stored case `{line}`", path.display());
+                    io::Error::new(io::ErrorKind::InvalidData, message)
+                })
+            })
+            .collect()
+    }
+
+    /// Save the run that produced `failed`, unless it is already stored.
+    pub fn save<M: ModelState>(&self, failed: &FailedState<M>) -> io::Result<()> {
+        let case = StoredCase {
+            seed: failed.seed,
+            max_steps: failed.max_steps,
+        };
+        if self.load::<M>()?.contains(&case) {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path::<M>();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "# Failing runs of {}.", any::type_name::<M>())?;
+            writeln!(file, "# Each line holds the seed and max_steps of a run.")?;
+        }
+        let message = failed.failure.message.lines().next().unwrap_or_default();
+        writeln!(file, "{:#x} {} # {message}", case.seed, case.max_steps)
+    }
+}
+
+fn parse_case(line: &str) -> Option<StoredCase> {
+    let (seed, max_steps) = line.split_once(char::is_whitespace)?;
+    Some(StoredCase {
+        seed: u64::from_str_radix(seed.strip_prefix("0x")?, 16).ok()?,
+        max_steps: max_steps.trim().parse().ok()?,
+    })
+}
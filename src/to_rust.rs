@@ -0,0 +1,242 @@
+use crate::{Differential, FailedState, FailureKind, ModelState, SystemUnderTest};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/// Write a value as a Rust expression that evaluates to an equal value, used to emit regression
+/// tests with `FailedState::regression_test`.
+///
+/// Expressions may refer to the value's type and its variants by name, so they are expected to
+/// compile in a scope where those names are visible.
+pub trait ToRust {
+    fn to_rust(&self) -> String;
+}
+
+impl ToRust for () {
+    fn to_rust(&self) -> String {
+        "()".to_owned()
+    }
+}
+
+impl ToRust for bool {
+    fn to_rust(&self) -> String {
+        self.to_string()
+    }
+}
+
+macro_rules! to_rust_int {
+    ($($t:ty),*) => {$(
+        impl ToRust for $t {
+            fn to_rust(&self) -> String {
+                format!("{self}{}", stringify!($t))
+            }
+        }
+    )*};
+}
+to_rust_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! to_rust_float {
+    ($($t:ident),*) => {$(
+        impl ToRust for $t {
+            fn to_rust(&self) -> String {
+                if self.is_nan() {
+                    format!("{}::NAN", stringify!($t))
+                } else if self.is_infinite() {
+                    let sign = if *self < 0.0 { "NEG_" } else { "" };
+                    format!("{}::{sign}INFINITY", stringify!($t))
+                } else {
+                    format!("{self:?}{}", stringify!($t))
+                }
+            }
+        }
+    )*};
+}
+to_rust_float!(f32, f64);
+
+impl ToRust for char {
+    fn to_rust(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl ToRust for String {
+    fn to_rust(&self) -> String {
+        format!("String::from({self:?})")
+    }
+}
+
+impl<T: ToRust> ToRust for Box<T> {
+    fn to_rust(&self) -> String {
+        format!("Box::new({})", (**self).to_rust())
+    }
+}
+
+impl<T: ToRust> ToRust for Option<T> {
+    fn to_rust(&self) -> String {
+        match self {
+            Some(x) => format!("Some({})", x.to_rust()),
+            None => "None".to_owned(),
+        }
+    }
+}
+
+impl<T: ToRust, E: ToRust> ToRust for Result<T, E> {
+    fn to_rust(&self) -> String {
+        match self {
+            Ok(x) => format!("Ok({})", x.to_rust()),
+            Err(e) => format!("Err({})", e.to_rust()),
+        }
+    }
+}
+
+/// Comma separated expressions for `items`.
+fn list<'a, T: ToRust + 'a>(items: impl IntoIterator<Item = &'a T>) -> String {
+    let items: Vec<String> = items.into_iter().map(T::to_rust).collect();
+    items.join(", ")
+}
+
+impl<T: ToRust, const N: usize> ToRust for [T; N] {
+    fn to_rust(&self) -> String {
+        format!("[{}]", list(self))
+    }
+}
+
+impl<T: ToRust> ToRust for Vec<T> {
+    fn to_rust(&self) -> String {
+        format!("vec![{}]", list(self))
+    }
+}
+
+impl<T: ToRust> ToRust for VecDeque<T> {
+    fn to_rust(&self) -> String {
+        format!("std::collections::VecDeque::from([{}])", list(self))
+    }
+}
+
+impl<T: ToRust> ToRust for BTreeSet<T> {
+    fn to_rust(&self) -> String {
+        format!("std::collections::BTreeSet::from([{}])", list(self))
+    }
+}
+
+impl<T: ToRust, S> ToRust for HashSet<T, S> {
+    fn to_rust(&self) -> String {
+        format!("std::collections::HashSet::from([{}])", list(self))
+    }
+}
+
+impl<K: ToRust, V: ToRust> ToRust for BTreeMap<K, V> {
+    fn to_rust(&self) -> String {
+        let entries: Vec<String> = self.iter().map(entry).collect();
+        format!("std::collections::BTreeMap::from([{}])", entries.join(", "))
+    }
+}
+
+impl<K: ToRust, V: ToRust, S> ToRust for HashMap<K, V, S> {
+    fn to_rust(&self) -> String {
+        let entries: Vec<String> = self.iter().map(entry).collect();
+        format!("std::collections::HashMap::from([{}])", entries.join(", "))
+    }
+}
+
+fn entry<K: ToRust, V: ToRust>((k, v): (&K, &V)) -> String {
+    format!("({}, {})", k.to_rust(), v.to_rust())
+}
+
+macro_rules! to_rust_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: ToRust),+> ToRust for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn to_rust(&self) -> String {
+                let ($($name,)+) = self;
+                let items = [$($name.to_rust()),+];
+                if items.len() == 1 {
+                    format!("({},)", items[0])
+                } else {
+                    format!("({})", items.join(", "))
+                }
+            }
+        }
+    };
+}
+to_rust_tuple!(A);
+to_rust_tuple!(A, B);
+to_rust_tuple!(A, B, C);
+to_rust_tuple!(A, B, C, D);
+to_rust_tuple!(A, B, C, D, E);
+to_rust_tuple!(A, B, C, D, E, F);
+
+impl<S: SystemUnderTest + ToRust> ToRust for Differential<S>
+where
+    S::Reference: ToRust,
+{
+    fn to_rust(&self) -> String {
+        format!(
+            "Differential {{ reference: {}, system: {} }}",
+            self.reference.to_rust(),
+            self.system.to_rust()
+        )
+    }
+}
+
+impl<M: ModelState + ToRust> FailedState<M>
+where
+    M::Step: ToRust,
+{
+    /// Render the shrunk trace as the source of a `#[test]` function named `name`, which builds
+    /// the initial state, applies each step, checks the invariant on every state as the checker
+    /// does, and expects the trace to fail as it did here.
+    ///
+    /// Panics are expected with `#[should_panic]`, which checks the message of a panic with a
+    /// string payload. Errors returned by the last step are compared by their `Debug` output,
+    /// and invariant violations by their message. A trace that crashed the process or timed out
+    /// is emitted as an ignored test, which crashes or hangs when run. The test is meant to be
+    /// pasted into a module where `modelcheck` and the model's types are in scope.
+    pub fn regression_test(&self, name: &str) -> String {
+        let mut lines = vec!["#[test]".to_owned()];
+        match &self.failure.kind {
+            FailureKind::Panic if self.failure.string_payload => {
+                let message = &self.failure.message;
+                lines.push(format!("#[should_panic(expected = {message:?})]"));
+            }
+            FailureKind::Panic => lines.push("#[should_panic]".to_owned()),
+            FailureKind::Crash(_) | FailureKind::Timeout(_) => {
+                let message = &self.failure.message;
+                lines.push(format!("#[ignore = {message:?}]"));
+            }
+            FailureKind::Error(_) | FailureKind::Invariant(_) => (),
+        }
+        lines.push(format!("fn {name}() {{"));
+        lines.push("    use modelcheck::ModelState as _;".to_owned());
+        lines.push(format!("    // seed {:#x}", self.seed));
+        lines.push(format!("    let mut state = {};", self.state.to_rust()));
+        // a step returning an error is applied last and separately, to check the error
+        let (steps, failing) = match (&self.failure.kind, self.steps.split_last()) {
+            (FailureKind::Error(error), Some((last, init))) => (init, Some((last, error))),
+            _ => (&self.steps[..], None),
+        };
+        // the invariant is checked on the initial state and after each step, as when checking
+        let check = "    state.invariant().unwrap();";
+        lines.push(check.to_owned());
+        for step in steps {
+            lines.push(format!("    state.step({}).unwrap();", step.to_rust()));
+            lines.push(check.to_owned());
+        }
+        if let Some((step, error)) = failing {
+            let error = format!("{error:?}");
+            lines.push(format!(
+                "    let error = state.step({}).unwrap_err();",
+                step.to_rust()
+            ));
+            lines.push(format!(
+                "    assert_eq!(format!(\"{{error:?}}\"), {error:?});"
+            ));
+        }
+        if let FailureKind::Invariant(error) = &self.failure.kind {
+            // the last check is the one that fails
+            lines.pop();
+            let error = format!("Err(String::from({error:?}))");
+            lines.push(format!("    assert_eq!(state.invariant(), {error});"));
+        }
+        lines.push("}\n".to_owned());
+        lines.join("\n")
+    }
+}